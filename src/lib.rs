@@ -0,0 +1,47 @@
+//! Model, level codes and solvers for the Toggle puzzle.
+//!
+//! A level code is parsed into a [`ToggleLevel`], which can be turned into a
+//! [`Grid`] to click buttons on, or handed to one of the solvers:
+//!
+//! ```no_run
+//! use hello_struct::{astar, linear, reader};
+//!
+//! let level = reader::parse("...".to_string()).unwrap();
+//! match linear::solve_linear(&level) {
+//!     linear::Result::Success(orders) => println!("{} clicks", orders.len()),
+//!     linear::Result::Fail => println!("unsolvable"),
+//!     linear::Result::Approximate(_) | linear::Result::NotLinear => {
+//!         if let astar::Result::Success(orders) = astar::run_optimal(&level) {
+//!             println!("{} clicks", orders.len());
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! New button types implement [`ButtonBehavior`] and are added with
+//! [`behavior::register`] under a subtype byte.
+//!
+//! The solvers are in the modules below and the types they share are
+//! re-exported here, everything else is internal.
+
+pub(crate) mod toggle;
+
+pub use toggle::{
+    analysis, astar, behavior, difficulty, generator, heuristic, hint, linear, reader, uniqueness,
+    verify, writer,
+};
+
+pub use toggle::analysis::Proof;
+pub use toggle::astar::{SolverConfig, SolverStats};
+pub use toggle::behavior::{BehaviorError, ButtonBehavior, Registry};
+pub use toggle::bitgrid::BitGrid;
+pub use toggle::core::{
+    ButtonFunction, Coordinate, Goal, Grid, OneArrow, RawSection, RayMode, ToggleLevel, Topology,
+    TwoArrow,
+};
+pub use toggle::difficulty::DifficultyReport;
+pub use toggle::generator::{generate, GeneratorConfig};
+pub use toggle::reader::{parse, parse_strict, ParseError};
+pub use toggle::utils::{BitArray, Rng};
+pub use toggle::verify::{replay, InvalidClick, Replay};
+pub use toggle::writer::{encode, EncodeError};
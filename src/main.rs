@@ -1,70 +1,691 @@
-use std::io;
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, io, process};
 
-use toggle::astar;
-use toggle::core::{Coordinate, Grid, ToggleLevel};
-use toggle::reader;
+use hello_struct::astar::SearchLimits;
+use hello_struct::generator::Target;
+use hello_struct::{
+    analysis, astar, difficulty, generator, hint, linear, reader, uniqueness, verify, writer,
+};
+use hello_struct::{
+    BitArray, ButtonFunction, Coordinate, GeneratorConfig, Goal, Grid, OneArrow, RawSection,
+    RayMode, SolverConfig, ToggleLevel, Topology, TwoArrow,
+};
 
-mod toggle;
+const USAGE: &str = "\
+Usage: hello_struct <command> [options] [CODE]
+
+Commands:
+  solve   [--optimal] [--max-nodes N]
+                               Print the clicks that clear the board
+  verify  --clicks \"x,y x,y\"   Replay clicks and check that the board is cleared
+  rate    [--max-nodes N]      Print the optimal clicks and difficulty metrics
+  render                       Draw the board, lit buttons are in brackets
+  info                         Dump the parsed fields of the level
+  hint    [--clicks \"x,y x,y\"]   Print the best next click after the given clicks
+  check                        Tell whether the level can be solved, and why not
+  unique                       Count the minimum-click solutions
+  minimize                     Remove the buttons the unique solution does not click
+  encode  --width W --height H --subtypes 1,2,.. --states 0110..
+          [--goal 01?1..] [--topology T] [--voids 0010.. [--rays M]]
+          [--min-clicks N] [--creator NAME]
+                               Print the level code of the given fields,
+                               ? marks goal cells that may end either way,
+                               1 in --voids marks holes in the board
+  generate --width W --height H [--subtypes 1,2,..] [--clicks N] [--seed N]
+           [--topology T] [--voids 0010.. [--rays M]]
+           [--min-clicks A-B] [--difficulty A-B] [--candidates N] [--max-nodes N]
+                               Print a random solvable level code, searching
+                               for one in the given ranges if any is set
+
+The level code is read from CODE, from --file PATH, or from stdin if
+neither is given (or CODE is -). The topology T is bounded, torus (edges
+wrap both ways) or cylinder (left and right edges wrap). The ray mode M is
+block (arrows stop at holes) or skip (arrows jump over them).";
+
+//
+// Arguments
+//
+struct Args {
+    command: String,
+    code: Option<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    fn parse(mut args: Vec<String>) -> Args {
+        if args.is_empty() {
+            usage_error("Missing command.");
+        }
+        let command = args.remove(0);
+
+        let mut code = None;
+        let mut options = Vec::new();
+
+        let mut iter = args.into_iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--optimal" {
+                options.push((arg, None));
+            } else if arg.starts_with("--") || arg == "-f" {
+                let value = iter.next();
+                if value.is_none() {
+                    usage_error(&format!("Missing value for {}.", arg));
+                }
+                options.push((arg, value));
+            } else if code.is_none() {
+                code = Some(arg);
+            } else {
+                usage_error(&format!("Unexpected argument {}.", arg));
+            }
+        }
+
+        Args {
+            command,
+            code,
+            options,
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(key, _)| key == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    fn required(&self, name: &str) -> &str {
+        match self.value(name) {
+            Some(value) => value,
+            None => usage_error(&format!("Missing {}.", name)),
+        }
+    }
+
+    fn read_level(&self) -> ToggleLevel {
+        let path = self.value("--file").or(self.value("-f"));
+
+        let code = match (path, self.code.as_deref()) {
+            (Some(path), _) => fs::read_to_string(path).unwrap_or_else(|e| {
+                fail(&format!("Failed to read {}: {}", path, e));
+            }),
+            (None, Some(code)) if code != "-" => code.to_string(),
+            _ => {
+                let mut code = String::new();
+                io::stdin()
+                    .read_line(&mut code)
+                    .expect("Failed to read input.");
+                code
+            }
+        };
+
+        match reader::parse(code) {
+            Ok(level) => level,
+            Err(e) => fail(&format!("Invalid level code: {}", e)),
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
 
 fn main() {
-    /*let args: Vec<String> = env::args().collect();
-    let code;
-    if args.len() == 2 {
-        code = args[1].clone();
+    let args = Args::parse(env::args().skip(1).collect());
+
+    match args.command.as_str() {
+        "solve" => solve(&args),
+        "verify" => verify(&args),
+        "rate" => rate(&args),
+        "render" => render(&args),
+        "info" => info(&args),
+        "encode" => encode(&args),
+        "generate" => generate(&args),
+        "hint" => hint(&args),
+        "check" => check(&args),
+        "unique" => unique(&args),
+        "minimize" => minimize(&args),
+        "help" | "--help" | "-h" => println!("{}", USAGE),
+        command => usage_error(&format!("Unknown command {}.", command)),
+    }
+}
+
+//
+// Commands
+//
+fn solve(args: &Args) {
+    let level = args.read_level();
+    let orders = find_solution(&level, args.flag("--optimal"), &search_limits(args));
+
+    println!("{} Clicks", orders.len());
+    for coord in orders {
+        println!("{},{}", coord.x, coord.y);
+    }
+}
+
+fn verify(args: &Args) {
+    let level = args.read_level();
+    let clicks = parse_clicks(args.required("--clicks"));
+
+    let result = verify::replay(&level, &clicks);
+    if let Some(invalid) = result.invalid {
+        fail(&invalid.to_string());
+    }
+
+    if result.cleared {
+        println!("Cleared in {} clicks", clicks.len());
     } else {
-        panic!("Please give level code via argument!");
-    }*/
+        println!("Not cleared");
+        process::exit(1);
+    }
+}
+
+fn rate(args: &Args) {
+    let level = args.read_level();
+    let report = match difficulty::rate_with(&level, &search_limits(args)) {
+        Some(report) => report,
+        None => fail("Failed to solve within the search limits!"),
+    };
+
+    println!("{} Clicks", report.optimal_clicks());
+    println!("Difficulty: {}", report.flip_score);
+    if let Some(gap) = report.min_clicks_gap() {
+        println!("Min clicks: {} ({:+} from optimal)", report.min_clicks, gap);
+    }
+    println!("Branching factor: {}", report.branching_factor);
+    if let Some(factor) = report.effective_branching_factor {
+        println!("Effective branching factor: {:.3}", factor);
+    }
+    match report.optimal_solutions {
+        Some(count) => println!("Optimal solutions: {}", count),
+        None => println!("Optimal solutions: too many to count"),
+    }
+    println!(
+        "Non-linear buttons: {:.1}%",
+        report.non_linear_ratio * 100.0
+    );
+    if let Some(stats) = report.stats {
+        println!(
+            "Search: {} expanded, {} generated, {} visited in {:?}",
+            stats.nodes_expanded, stats.nodes_generated, stats.visited, stats.elapsed
+        );
+    }
+}
 
-    let mut code = String::new();
-    io::stdin()
-        .read_line(&mut code)
-        .expect("Failed to read input.");
+fn render(args: &Args) {
+    let level = args.read_level();
+    print!("{}", render_grid(&Grid::from_level(&level)));
+}
 
-    let level = reader::parse(code);
+fn info(args: &Args) {
+    let level = args.read_level();
 
-    let result = astar::run_astar(&level);
-    match result {
-        astar::Result::Success(orders) => {
-            calculate_difficulty(&level, orders);
+    println!("Header: 0x{:02x}", level.header);
+    println!("Size: {}x{}", level.width, level.height);
+    println!("Topology: {:?}", level.topology);
+    println!("Rays: {:?}", level.ray_mode);
+    println!("Min clicks: {}", level.min_clicks);
+    println!("Creator: {}", level.creator);
+
+    println!("Subtypes:");
+    for y in (0..level.height).rev() {
+        let row: Vec<String> = (0..level.width)
+            .map(|x| format!("{:2}", level.subtypes[(y * level.width + x) as usize]))
+            .collect();
+        println!("  {}", row.join(" "));
+    }
+
+    println!("States:");
+    print_cells(&level, |cell| match level.states.get(cell) {
+        true => '1',
+        false => '0',
+    });
+
+    if let Some(voids) = &level.voids {
+        println!("Voids:");
+        print_cells(&level, |cell| match voids.get(cell) {
+            true => '1',
+            false => '0',
+        });
+    }
+
+    if let Some(goal) = &level.goal {
+        println!("Goal:");
+        print_cells(&level, |cell| {
+            match (goal.care.get(cell), goal.target.get(cell)) {
+                (false, _) => '?',
+                (true, true) => '1',
+                (true, false) => '0',
+            }
+        });
+    }
+
+    for RawSection { tag, data } in level.unknown_sections.iter() {
+        println!("Unknown section 0x{:02x}: {} bytes", tag, data.len());
+    }
+}
+
+fn encode(args: &Args) {
+    let mut level = ToggleLevel::new();
+    level.width = parse_dimension(args.required("--width"), "--width");
+    level.height = parse_dimension(args.required("--height"), "--height");
+
+    let total_buttons = level.num_cells();
+
+    // Subtypes and states are given row by row from the top, like `render` draws them
+    let subtypes: Vec<u8> = args
+        .required("--subtypes")
+        .split(',')
+        .map(|subtype| parse_number(subtype.trim(), "--subtypes"))
+        .collect();
+    if subtypes.len() != total_buttons {
+        fail(&format!(
+            "--subtypes has {} entries, expected {}",
+            subtypes.len(),
+            total_buttons
+        ));
+    }
+
+    level.subtypes = vec![0; total_buttons];
+    for (i, subtype) in subtypes.iter().enumerate() {
+        let x = i as u32 % level.width;
+        let y = level.height - 1 - i as u32 / level.width;
+        level.subtypes[(y * level.width + x) as usize] = *subtype;
+    }
+
+    level.states = BitArray::new(total_buttons);
+    for (cell, c) in parse_cells(&level, args.required("--states"), "--states") {
+        level.states.set(cell, c == '1');
+    }
+
+    if let Some(text) = args.value("--goal") {
+        let mut goal = Goal::new(total_buttons);
+        for (cell, c) in parse_cells(&level, text, "--goal") {
+            goal.target.set(cell, c == '1');
+            goal.care.set(cell, c != '?');
         }
-        astar::Result::Fail => {
-            println!("Failed to solve!");
+        level.goal = Some(goal);
+    }
+
+    if let Some(topology) = args.value("--topology") {
+        level.topology = parse_topology(topology);
+    }
+    if let Some(text) = args.value("--voids") {
+        level.voids = Some(parse_voids(&level, text));
+    }
+    if let Some(rays) = args.value("--rays") {
+        level.ray_mode = parse_ray_mode(rays);
+    }
+
+    if let Some(min_clicks) = args.value("--min-clicks") {
+        level.min_clicks = parse_number(min_clicks, "--min-clicks");
+    }
+    if let Some(creator) = args.value("--creator") {
+        level.creator = creator.to_string();
+    }
+
+    match writer::encode(&level) {
+        Ok(code) => println!("{}", code),
+        Err(e) => fail(&format!("Failed to encode the level: {}", e)),
+    }
+}
+
+fn generate(args: &Args) {
+    let width = parse_dimension(args.required("--width"), "--width");
+    let height = parse_dimension(args.required("--height"), "--height");
+    let seed = match args.value("--seed") {
+        Some(seed) => parse_number(seed, "--seed"),
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64),
+    };
+
+    let mut config = GeneratorConfig::new(width, height, seed);
+    if let Some(subtypes) = args.value("--subtypes") {
+        config.subtypes = subtypes
+            .split(',')
+            .map(|subtype| parse_number(subtype.trim(), "--subtypes"))
+            .collect();
+    }
+    if let Some(clicks) = args.value("--clicks") {
+        config.scramble_clicks = parse_number(clicks, "--clicks");
+    }
+    if let Some(topology) = args.value("--topology") {
+        config.topology = parse_topology(topology);
+    }
+    if let Some(text) = args.value("--voids") {
+        let mut shape = ToggleLevel::new();
+        shape.width = width;
+        shape.height = height;
+        config.voids = Some(parse_voids(&shape, text));
+    }
+    if let Some(rays) = args.value("--rays") {
+        config.ray_mode = parse_ray_mode(rays);
+    }
+
+    // Levels that take long to solve are skipped rather than waited for.
+    // Buttons that move lights make the optimal search much slower.
+    config.limits.max_nodes = Some(match args.value("--max-nodes") {
+        Some(max_nodes) => parse_number(max_nodes, "--max-nodes"),
+        None => 20_000,
+    });
+
+    if args.value("--min-clicks").is_none() && args.value("--difficulty").is_none() {
+        match generator::generate(&config) {
+            Some(generated) => {
+                println!("{}", generated.code);
+                println!("{} Clicks (seed {})", generated.level.min_clicks, seed);
+            }
+            None => fail("Failed to generate a level, the search may have hit --max-nodes"),
+        }
+        return;
+    }
+
+    let mut target = Target::new(
+        parse_range(
+            args.value("--min-clicks").unwrap_or("0-255"),
+            "--min-clicks",
+        ),
+        parse_range(
+            args.value("--difficulty").unwrap_or("0-1000"),
+            "--difficulty",
+        ),
+    );
+    if let Some(candidates) = args.value("--candidates") {
+        target.max_candidates = parse_number(candidates, "--candidates");
+    }
+    let output = generator::generate_targeted(&config, &target);
+    match output.result {
+        Some(generated) => {
+            println!("{}", generated.code);
+            println!(
+                "{} Clicks, difficulty {:.2} (seed {}, {} candidates)",
+                generated.level.min_clicks,
+                difficulty::flip_score(&generated.level, &generated.solution),
+                seed,
+                output.candidates
+            );
         }
+        None => fail(&format!(
+            "No level found in {} candidates (seed {})",
+            output.candidates, seed
+        )),
     }
 }
 
-fn calculate_difficulty(level: &ToggleLevel, orders: Vec<Coordinate>) {
-    let mut diff: Vec<u32> = Vec::new();
+fn hint(args: &Args) {
+    let level = args.read_level();
+    let history = parse_clicks(args.value("--clicks").unwrap_or(""));
 
-    let mut grid = Grid::from_level(level);
-    let total_buttons = grid.width() * grid.height();
-    for _ in 0..total_buttons {
-        diff.push(0);
+    match hint::hint_after(&level, &history) {
+        hint::Result::Next(hint) => {
+            println!("{},{}", hint.click.x, hint.click.y);
+            println!("{} clicks left", hint.remaining);
+        }
+        hint::Result::Cleared => println!("Already cleared"),
+        hint::Result::Unsolvable => fail("No clicks clear the board from here"),
+        hint::Result::Aborted(reason) => fail(&format!("Search stopped ({:?})", reason)),
+        hint::Result::InvalidClick(invalid) => fail(&invalid.to_string()),
+    }
+}
+
+fn check(args: &Args) {
+    let level = args.read_level();
+    match analysis::analyze(&level) {
+        analysis::Result::Solvable => println!("Solvable"),
+        analysis::Result::Unsolvable(proof) => {
+            println!("Unsolvable: {}", proof);
+            process::exit(1);
+        }
+        analysis::Result::Unknown => {
+            println!("Unknown: too many reachable states to decide");
+            process::exit(3);
+        }
     }
+}
 
-    let mut previous_state = grid.get_states();
+fn unique(args: &Args) {
+    let level = args.read_level();
+    let count = match uniqueness::count_solutions(&level, u64::MAX) {
+        Some(count) => count,
+        None => fail("Failed to solve within the search limits!"),
+    };
 
-    println!("{} Clicks", orders.len());
-    for ord in orders {
-        grid.click(ord.x, ord.y);
-        let current_state = grid.get_states();
+    let bound = if count.complete { "" } else { "at least " };
+    println!(
+        "{}{} solutions of {} clicks",
+        bound,
+        count.count,
+        count.solution.len()
+    );
+    for coord in count.solution {
+        println!("{},{}", coord.x, coord.y);
+    }
+}
+
+fn minimize(args: &Args) {
+    let level = args.read_level();
+    match uniqueness::minimize(&level) {
+        Some(minimized) => {
+            match writer::encode(&minimized.level) {
+                Ok(code) => println!("{}", code),
+                Err(e) => fail(&format!("Failed to encode the level: {}", e)),
+            }
+            println!("Removed {} buttons", minimized.removed.len());
+        }
+        None => fail("The level has no unique solution or could not be solved exactly"),
+    }
+}
+
+//
+// Helpers
+//
+fn parse_number<T: std::str::FromStr>(text: &str, name: &str) -> T {
+    match text.parse() {
+        Ok(value) => value,
+        Err(_) => fail(&format!("Invalid number {} for {}", text, name)),
+    }
+}
+
+// Width and height are stored in one byte each in a level code
+fn parse_dimension(text: &str, name: &str) -> u32 {
+    match parse_number(text, name) {
+        value @ 1..=255 => value,
+        value => fail(&format!(
+            "{} must be between 1 and 255, got {}",
+            name, value
+        )),
+    }
+}
+
+fn parse_topology(text: &str) -> Topology {
+    match text {
+        "bounded" => Topology::Bounded,
+        "torus" => Topology::Torus,
+        "cylinder" => Topology::Cylinder,
+        _ => fail(&format!("Unknown topology {}", text)),
+    }
+}
+
+fn parse_ray_mode(text: &str) -> RayMode {
+    match text {
+        "block" => RayMode::Block,
+        "skip" => RayMode::Skip,
+        _ => fail(&format!("Unknown ray mode {}", text)),
+    }
+}
+
+fn parse_voids(level: &ToggleLevel, text: &str) -> BitArray {
+    let mut result = BitArray::new(level.num_cells());
+    for (cell, c) in parse_cells(level, text, "--voids") {
+        result.set(cell, c == '1');
+    }
+    result
+}
+
+// Ranges are written as "min-max" or as a single value
+fn parse_range<T: std::str::FromStr + Copy>(text: &str, name: &str) -> RangeInclusive<T> {
+    match text.split_once('-') {
+        Some((start, end)) => parse_number(start, name)..=parse_number(end, name),
+        None => {
+            let value = parse_number(text, name);
+            value..=value
+        }
+    }
+}
 
-        for i in 0..(total_buttons as usize) {
-            if current_state.get(i) != previous_state.get(i) {
-                diff[i] += 1;
+// One character per cell, row by row from the top, paired with the
+// column-major index of the cell
+fn parse_cells(level: &ToggleLevel, text: &str, name: &str) -> Vec<(usize, char)> {
+    let total_buttons = level.num_cells();
+    let cells: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if cells.len() != total_buttons {
+        fail(&format!(
+            "{} has {} entries, expected {}",
+            name,
+            cells.len(),
+            total_buttons
+        ));
+    }
+
+    cells
+        .into_iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let x = i as u32 % level.width;
+            let y = level.height - 1 - i as u32 / level.width;
+            ((x * level.height + y) as usize, c)
+        })
+        .collect()
+}
+
+fn print_cells(level: &ToggleLevel, symbol: impl Fn(usize) -> char) {
+    for y in (0..level.height).rev() {
+        let row: String = (0..level.width)
+            .map(|x| symbol((x * level.height + y) as usize))
+            .collect();
+        println!("  {}", row);
+    }
+}
+
+// Clicks are written as "x,y" pairs separated by spaces or semicolons
+fn parse_clicks(text: &str) -> Vec<Coordinate> {
+    text.split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once(',') {
+            Some((x, y)) => {
+                Coordinate::new(parse_number(x, "--clicks"), parse_number(y, "--clicks"))
             }
+            None => fail(&format!("Invalid click {}, expected x,y", pair)),
+        })
+        .collect()
+}
+
+// Limits of the searches that solve a level, from --max-nodes
+fn search_limits(args: &Args) -> SearchLimits {
+    let mut limits = SearchLimits::new();
+    limits.max_nodes = Some(match args.value("--max-nodes") {
+        Some(max_nodes) => parse_number(max_nodes, "--max-nodes"),
+        None => astar::DEFAULT_MAX_NODES,
+    });
+    limits
+}
+
+fn find_solution(level: &ToggleLevel, optimal: bool, limits: &SearchLimits) -> Vec<Coordinate> {
+    // A failed search has to visit every reachable state, so reject what can be proven first
+    if let analysis::Result::Unsolvable(proof) = analysis::analyze_bounded(level, 100_000) {
+        fail(&format!("Unsolvable: {}", proof));
+    }
+
+    // Levels made only of XOR buttons are solved exactly without searching
+    let result = match linear::solve_linear(level) {
+        linear::Result::Success(orders) => astar::Result::Success(orders),
+        linear::Result::Fail => astar::Result::Fail,
+        linear::Result::Approximate(_) | linear::Result::NotLinear if optimal => {
+            let mut config = SolverConfig::optimal(level);
+            config.limits = *limits;
+            astar::run_astar_with(level, &config).result
         }
-        previous_state = current_state;
+        linear::Result::Approximate(orders) => astar::Result::Success(orders),
+        linear::Result::NotLinear => {
+            let mut config = SolverConfig::new();
+            config.limits = *limits;
+            astar::run_astar_with(level, &config).result
+        }
+    };
+
+    match result {
+        astar::Result::Success(orders) => orders,
+        astar::Result::Fail => fail("Failed to solve!"),
+        astar::Result::Aborted(partial) => fail(&format!(
+            "Search stopped ({:?}) {} cells away",
+            partial.reason, partial.diff
+        )),
     }
+}
+
+fn symbol(func: &ButtonFunction) -> char {
+    match func {
+        ButtonFunction::Nothing => '.',
+        ButtonFunction::OneArrow(OneArrow::Up) => '↑',
+        ButtonFunction::OneArrow(OneArrow::Down) => '↓',
+        ButtonFunction::OneArrow(OneArrow::Left) => '←',
+        ButtonFunction::OneArrow(OneArrow::Right) => '→',
+        ButtonFunction::OneArrow(OneArrow::LeftUp) => '↖',
+        ButtonFunction::OneArrow(OneArrow::RightUp) => '↗',
+        ButtonFunction::OneArrow(OneArrow::LeftDown) => '↙',
+        ButtonFunction::OneArrow(OneArrow::RightDown) => '↘',
+        ButtonFunction::TwoArrow(TwoArrow::BothHorizontal) => '↔',
+        ButtonFunction::TwoArrow(TwoArrow::BothVertical) => '↕',
+        ButtonFunction::TwoArrow(TwoArrow::LeftUpRightDown) => '⤡',
+        ButtonFunction::TwoArrow(TwoArrow::LeftDownRightUp) => '⤢',
+        ButtonFunction::FourArrow => '+',
+        ButtonFunction::Rotate(true) => '↻',
+        ButtonFunction::Rotate(false) => '↺',
+        ButtonFunction::Symmetry(true) => '|',
+        ButtonFunction::Symmetry(false) => '-',
+        ButtonFunction::Shift(false) => '«',
+        ButtonFunction::Shift(true) => '»',
+        ButtonFunction::AroundEight => '#',
+        ButtonFunction::Knight => 'N',
+        ButtonFunction::ColumnShift(true) => '⇑',
+        ButtonFunction::ColumnShift(false) => '⇓',
+        ButtonFunction::CrossDiagonal => 'x',
+        ButtonFunction::BlockFlip => '%',
+        ButtonFunction::Custom(_) => '*',
+    }
+}
 
-    let mut mul: f64 = 1.0;
-    for i in 0..diff.len() {
-        if diff[i] != 0 {
-            mul *= diff[i] as f64;
+fn render_grid(grid: &Grid) -> String {
+    let mut result = String::new();
+
+    for y in (0..grid.height()).rev() {
+        result.push_str(&format!("{:3} ", y));
+        for x in 0..grid.width() {
+            let btn = grid.at(x, y).unwrap();
+            if btn.is_void {
+                result.push_str("   ");
+                continue;
+            }
+            let (left, right) = if btn.is_on { ('[', ']') } else { (' ', ' ') };
+            result.push_str(&format!("{}{}{}", left, symbol(&btn.func), right));
         }
+        result.push('\n');
+    }
+
+    result.push_str("    ");
+    for x in 0..grid.width() {
+        result.push_str(&format!("{:^3}", x));
     }
-    mul = f64::powf(mul, 1.0 / total_buttons as f64);
+    result.push('\n');
 
-    let difficulty = (mul - 1.0) * 10.0;
-    println!("Difficulty: {}", difficulty);
+    result
 }
@@ -0,0 +1,334 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use super::bitgrid::BitGrid;
+use super::core::{ButtonFunction, Coordinate, Goal, Grid, ToggleLevel};
+use super::linear;
+
+// States the bounded search may visit before giving up
+const MAX_STATES: usize = 1_000_000;
+
+pub enum Result {
+    Solvable,
+    Unsolvable(Proof),
+    /// No check could decide it within the state bound
+    Unknown,
+}
+
+/// Why a level cannot be solved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proof {
+    /// Cells off their goal that no button affects
+    Untouched(Vec<Coordinate>),
+    /// Cells in which every button toggles an even number, while an odd number
+    /// are off their goal
+    Parity(Vec<Coordinate>),
+    /// Cells whose lights every click only moves between each other, so `lit`
+    /// of them stay on while the goal wants `goal` on
+    Invariant {
+        cells: Vec<Coordinate>,
+        lit: u32,
+        goal: u32,
+    },
+    /// Number of states reachable from the start, none of them cleared
+    Exhausted(usize),
+}
+
+fn write_cells(f: &mut fmt::Formatter<'_>, cells: &[Coordinate]) -> fmt::Result {
+    for (i, cell) in cells.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{},{}", cell.x, cell.y)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Proof::Untouched(cells) => {
+                write!(f, "No button changes the cells ")?;
+                write_cells(f, cells)?;
+                write!(f, " that are off their goal")
+            }
+            Proof::Parity(cells) => {
+                write!(f, "Every button toggles an even number of the cells ")?;
+                write_cells(f, cells)?;
+                write!(f, " but an odd number of them are off their goal")
+            }
+            Proof::Invariant { cells, lit, goal } => {
+                write!(f, "Clicks only move lights between the cells ")?;
+                write_cells(f, cells)?;
+                write!(f, ", so {} of them stay lit but the goal has {}", lit, goal)
+            }
+            Proof::Exhausted(states) => write!(
+                f,
+                "None of the {} states reachable from the start is cleared",
+                states
+            ),
+        }
+    }
+}
+
+/// Decides whether the level can be cleared, trying cheap checks before
+/// searching every reachable state up to a bound
+pub fn analyze(level: &ToggleLevel) -> Result {
+    analyze_bounded(level, MAX_STATES)
+}
+
+pub fn analyze_bounded(level: &ToggleLevel, max_states: usize) -> Result {
+    let grid = Grid::from_level(level);
+    let states = grid.get_states();
+    let goal = level.goal();
+    let is_off_goal =
+        |cell: usize| goal.care.get(cell) && states.get(cell) != goal.target.get(cell);
+    let height = grid.height();
+    let coord_of = |cell: usize| Coordinate::new(cell as i32 / height, cell as i32 % height);
+
+    let mut buttons = Vec::new();
+    let mut is_linear = true;
+    let mut touched = vec![false; states.len()];
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            let func = &grid.at(x, y).unwrap().func;
+            if let ButtonFunction::Nothing = func {
+                continue;
+            }
+
+            buttons.push(Coordinate::new(x, y));
+            is_linear &= func.is_linear();
+            for cell in grid.affected_cells(x, y) {
+                touched[(cell.x * height + cell.y) as usize] = true;
+            }
+        }
+    }
+
+    let untouched: Vec<Coordinate> = (0..states.len())
+        .filter(|&cell| is_off_goal(cell) && !touched[cell])
+        .map(coord_of)
+        .collect();
+    if !untouched.is_empty() {
+        return Result::Unsolvable(Proof::Untouched(untouched));
+    }
+
+    // Linear levels are decided exactly by Gaussian elimination
+    if is_linear {
+        return match linear::parity_witness(level) {
+            Some(cells) => Result::Unsolvable(Proof::Parity(cells)),
+            None => Result::Solvable,
+        };
+    }
+
+    let mut bit_grid = BitGrid::from_grid(&grid);
+    bit_grid.set_goal(&goal);
+    for region in invariant_regions(&bit_grid, &buttons, &goal) {
+        let lit = region.iter().filter(|&&cell| states.get(cell)).count() as u32;
+        let wanted = region.iter().filter(|&&cell| goal.target.get(cell)).count() as u32;
+        if lit != wanted {
+            let cells = region.into_iter().map(coord_of).collect();
+            return Result::Unsolvable(Proof::Invariant {
+                cells,
+                lit,
+                goal: wanted,
+            });
+        }
+    }
+
+    match explore(bit_grid, &buttons, max_states) {
+        Explored::Cleared => Result::Solvable,
+        Explored::Exhausted(states) => Result::Unsolvable(Proof::Exhausted(states)),
+        Explored::TooMany => Result::Unknown,
+    }
+}
+
+// Groups of cared cells whose number of lit cells no click changes: no button
+// toggles or clears them, and every click permutes each group onto itself
+fn invariant_regions(bit_grid: &BitGrid, buttons: &[Coordinate], goal: &Goal) -> Vec<Vec<usize>> {
+    let num_cells = (bit_grid.width() * bit_grid.height()) as usize;
+    let mut in_region: Vec<bool> = (0..num_cells).map(|cell| goal.care.get(cell)).collect();
+
+    for coord in buttons.iter() {
+        let action = bit_grid.action(coord.x, coord.y);
+
+        let mut is_destination = vec![false; num_cells];
+        let mut is_source = vec![false; num_cells];
+        for &(destination, source) in action.moves() {
+            is_destination[destination] = true;
+            match source {
+                Some(source) => is_source[source] = true,
+                None => in_region[destination] = false,
+            }
+        }
+
+        for cell in 0..num_cells {
+            // A destination that is not read loses its light, a source that is
+            // not overwritten gets copied
+            if action.toggles(cell) || is_destination[cell] != is_source[cell] {
+                in_region[cell] = false;
+            }
+        }
+    }
+
+    // Lights may not move across the border of the region
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for coord in buttons.iter() {
+            for &(destination, source) in bit_grid.action(coord.x, coord.y).moves() {
+                let source = match source {
+                    Some(source) => source,
+                    None => continue,
+                };
+                if in_region[destination] != in_region[source] {
+                    in_region[destination] = false;
+                    in_region[source] = false;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Cells linked by some move belong to the same group
+    let mut parent: Vec<usize> = (0..num_cells).collect();
+    for coord in buttons.iter() {
+        for &(destination, source) in bit_grid.action(coord.x, coord.y).moves() {
+            if let Some(source) = source.filter(|_| in_region[destination]) {
+                let a = find_root(&mut parent, destination);
+                let b = find_root(&mut parent, source);
+                parent[a] = b;
+            }
+        }
+    }
+
+    let mut result: Vec<Vec<usize>> = Vec::new();
+    let mut group_of = vec![usize::MAX; num_cells];
+    for cell in (0..num_cells).filter(|&cell| in_region[cell]) {
+        let root = find_root(&mut parent, cell);
+        if group_of[root] == usize::MAX {
+            group_of[root] = result.len();
+            result.push(Vec::new());
+        }
+        result[group_of[root]].push(cell);
+    }
+
+    result
+}
+
+fn find_root(parent: &mut [usize], cell: usize) -> usize {
+    let mut root = cell;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[cell] = root;
+    root
+}
+
+enum Explored {
+    Cleared,
+    /// Every reachable state was visited, this many of them
+    Exhausted(usize),
+    TooMany,
+}
+
+// Breadth-first search over the reachable states, up to `max_states` of them
+fn explore(mut bit_grid: BitGrid, buttons: &[Coordinate], max_states: usize) -> Explored {
+    if bit_grid.is_reached() {
+        return Explored::Cleared;
+    }
+
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(bit_grid.to_key());
+    queue.push_back(bit_grid.to_key());
+
+    while let Some(key) = queue.pop_front() {
+        for coord in buttons.iter() {
+            bit_grid.set_key(&key);
+            bit_grid.click(coord.x, coord.y);
+            if bit_grid.is_reached() {
+                return Explored::Cleared;
+            }
+
+            let new_key = bit_grid.to_key();
+            if !visited.contains(&new_key) {
+                if visited.len() >= max_states {
+                    return Explored::TooMany;
+                }
+                visited.insert(new_key.clone());
+                queue.push_back(new_key);
+            }
+        }
+    }
+
+    Explored::Exhausted(visited.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::testing::{distances, random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::Rng;
+
+    #[test]
+    fn proofs_match_breadth_first_search() {
+        let pools: [Vec<u8>; 3] = [
+            (0..26).collect(),
+            vec![0, 1, 5, 11, 12],
+            vec![0, 14, 15, 16, 17, 18, 19, 22, 23, 25],
+        ];
+        let mut rng = Rng::new(61);
+        let mut seen = [0; 5];
+        for subtypes in pools.iter() {
+            for (width, height) in [(2, 2), (3, 2), (3, 3)] {
+                for i in 0..40 {
+                    let mut level = random_level(width, height, subtypes, &mut rng);
+                    if i % 2 == 1 {
+                        random_goal(&mut level, &mut rng);
+                    }
+
+                    let clicks = shortest_solution(&level);
+                    match analyze_bounded(&level, 1000) {
+                        Result::Solvable => {
+                            assert!(clicks.is_some());
+                            seen[0] += 1;
+                        }
+                        Result::Unsolvable(proof) => {
+                            assert_eq!(clicks, None, "{}", proof);
+                            match proof {
+                                Proof::Untouched(_) => seen[1] += 1,
+                                Proof::Parity(_) => seen[2] += 1,
+                                Proof::Invariant { .. } => seen[3] += 1,
+                                Proof::Exhausted(states) => {
+                                    assert_eq!(states, distances(&level).len());
+                                    seen[4] += 1;
+                                }
+                            }
+                        }
+                        Result::Unknown => panic!("{} cells fit the bound", level.num_cells()),
+                    }
+                }
+            }
+        }
+        assert!(seen.iter().all(|&count| count > 0), "{:?}", seen);
+    }
+
+    #[test]
+    fn state_bound_leaves_it_unknown() {
+        let mut rng = Rng::new(67);
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut tested = 0;
+        for _ in 0..100 {
+            let mut level = random_level(3, 2, &subtypes, &mut rng);
+            random_goal(&mut level, &mut rng);
+            if let Result::Unsolvable(Proof::Exhausted(states)) = analyze(&level) {
+                assert!(matches!(
+                    analyze_bounded(&level, states - 1),
+                    Result::Unknown
+                ));
+                tested += 1;
+            }
+        }
+        assert!(tested > 0);
+    }
+}
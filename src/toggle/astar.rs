@@ -1,175 +1,535 @@
-use crate::toggle::{core::ButtonFunction, utils::BitArray};
+use crate::toggle::core::ButtonFunction;
+use crate::toggle::utils::{BitArray, StateKey};
 use priority_queue::PriorityQueue;
-use std::{collections::HashMap, hash::Hash};
-use stopwatch::Stopwatch;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use super::bitgrid::BitGrid;
 use super::core::{Coordinate, Grid, ToggleLevel};
+use super::heuristic::{Admissible, Heuristic, SquaredHamming};
 
-#[derive(PartialEq, Eq, Hash, Clone)]
-struct SolverItem {
-    state: BitArray,
+const NO_PARENT: usize = usize::MAX;
+
+// Search tree node. Nodes live in one arena and point at their parent,
+// so the clicks are only rebuilt once a solution is found.
+struct SolverNode {
+    key: StateKey,
+    parent: usize,
     coord: Coordinate,
-    orders: Vec<Coordinate>,
+    clicks: usize,
 }
 
-impl SolverItem {
-    fn new() -> SolverItem {
-        SolverItem {
-            state: BitArray::new(0),
-            coord: Coordinate::new(0, 0),
-            orders: Vec::new(),
-        }
+fn build_orders(nodes: &[SolverNode], mut index: usize) -> Vec<Coordinate> {
+    let mut result = Vec::new();
+    while nodes[index].parent != NO_PARENT {
+        result.push(nodes[index].coord);
+        index = nodes[index].parent;
     }
+    result.reverse();
+    result
 }
 
 pub enum Result {
     Success(Vec<Coordinate>),
     Fail,
+    Aborted(Partial),
 }
 
-fn calculate_diff(b1: &BitArray, b2: &BitArray) -> i32 {
-    let mut diff = 0;
-    assert_eq!(b1.len(), b2.len());
+/// Why a search stopped before finishing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Nodes,
+    Time,
+    Memory,
+    Cancelled,
+}
 
-    for i in 0..b2.len() {
-        if b1.get(i) != b2.get(i) {
-            diff += 1;
+/// Closest state to the target reached before a limit was hit
+pub struct Partial {
+    pub reason: Limit,
+    pub orders: Vec<Coordinate>,
+    pub state: BitArray,
+    pub diff: i32,
+    pub stats: SolverStats,
+}
+
+//
+// Solver Stats
+//
+#[derive(Debug, Clone, Copy)]
+pub struct SolverStats {
+    pub nodes_expanded: usize,
+    pub nodes_generated: usize,
+    /// Largest size of the priority queue
+    pub peak_open: usize,
+    /// Number of distinct states seen
+    pub visited: usize,
+    pub elapsed: Duration,
+    pub solution_length: Option<usize>,
+}
+
+impl SolverStats {
+    pub fn new() -> SolverStats {
+        SolverStats {
+            nodes_expanded: 0,
+            nodes_generated: 0,
+            peak_open: 0,
+            visited: 0,
+            elapsed: Duration::ZERO,
+            solution_length: None,
         }
     }
+}
 
-    diff
+impl Default for SolverStats {
+    fn default() -> SolverStats {
+        SolverStats::new()
+    }
 }
 
-fn calculate_cost(a: &SolverItem, target_state: &BitArray) -> i32 {
-    let solver_state = &a.state;
-    let diff = calculate_diff(solver_state, target_state);
+pub struct SolverOutput {
+    pub result: Result,
+    pub stats: SolverStats,
+}
 
-    diff * diff + 2 * a.orders.len() as i32
+/// Receives the statistics every `progress_interval` expanded items
+pub trait SearchObserver {
+    fn on_progress(&mut self, stats: &SolverStats);
 }
 
-fn is_arrow(a: ButtonFunction) -> bool {
-    match a {
-        ButtonFunction::OneArrow(_) | ButtonFunction::TwoArrow(_) | ButtonFunction::FourArrow => {
-            true
+impl<F: FnMut(&SolverStats)> SearchObserver for F {
+    fn on_progress(&mut self, stats: &SolverStats) {
+        self(stats)
+    }
+}
+
+//
+// Search Limits
+//
+/// Expanded nodes after which searches that should answer within seconds give up
+pub const DEFAULT_MAX_NODES: usize = 200_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    /// Maximum number of expanded items
+    pub max_nodes: Option<usize>,
+    pub max_time: Option<Duration>,
+    /// Rough upper bound on the bytes held by the open and visited sets
+    pub max_memory: Option<usize>,
+}
+
+impl SearchLimits {
+    pub fn new() -> SearchLimits {
+        SearchLimits {
+            max_nodes: None,
+            max_time: None,
+            max_memory: None,
         }
-        _ => false,
     }
 }
 
-pub fn run_astar(level: &ToggleLevel) -> Result {
-    let mut result = Result::Fail;
+impl Default for SearchLimits {
+    fn default() -> SearchLimits {
+        SearchLimits::new()
+    }
+}
 
-    let mut grid = Grid::from_level(&level);
-    let mut previous_state = grid.get_states();
+/// Shared flag that stops a running search from another thread
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
 
-    grid.set_all_state(false);
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
 
-    let target_state = grid.get_states();
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
 
-    let mut visited = HashMap::new();
-    visited.insert(previous_state.to_base64(), true);
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
 
-    let mut pq = PriorityQueue::new();
-    for y in 0..grid.height() {
-        for x in 0..grid.width() {
-            let mut is_nothing: bool = false;
+impl Default for CancelToken {
+    fn default() -> CancelToken {
+        CancelToken::new()
+    }
+}
 
-            match grid.at(x, y).unwrap().func {
-                ButtonFunction::Nothing => {
-                    is_nothing = true;
-                }
-                _ => {}
+/// Order of items whose costs are equal
+pub enum TieBreak {
+    None,
+    Deeper,
+    Shallower,
+}
+
+//
+// Solver Config
+//
+pub struct SolverConfig {
+    pub heuristic: Box<dyn Heuristic>,
+    /// Weight of the clicks made so far in the cost
+    pub g_weight: i32,
+    pub tie_break: TieBreak,
+    /// Never click buttons without a function
+    pub skip_nothing: bool,
+    /// Never click the same linear button twice in a row, it cancels out
+    pub skip_repeated_linear: bool,
+    pub limits: SearchLimits,
+    pub cancel: Option<CancelToken>,
+    /// Expanded items between two observer calls, 0 disables them
+    pub progress_interval: usize,
+}
+
+impl SolverConfig {
+    /// Fast greedy search, solutions are not always the shortest
+    pub fn new() -> SolverConfig {
+        SolverConfig {
+            heuristic: Box::new(SquaredHamming),
+            g_weight: 2,
+            tie_break: TieBreak::None,
+            skip_nothing: true,
+            skip_repeated_linear: true,
+            limits: SearchLimits::new(),
+            cancel: None,
+            progress_interval: 10000,
+        }
+    }
+
+    /// Search that always returns a minimum-click solution
+    pub fn optimal(level: &ToggleLevel) -> SolverConfig {
+        SolverConfig {
+            heuristic: Box::new(Admissible::with_goal(
+                &Grid::from_level(level),
+                &level.goal(),
+            )),
+            g_weight: 1,
+            tie_break: TieBreak::Deeper,
+            skip_nothing: true,
+            skip_repeated_linear: true,
+            limits: SearchLimits::new(),
+            cancel: None,
+            progress_interval: 10000,
+        }
+    }
+
+    fn priority(&self, grid: &BitGrid, clicks: usize) -> (i32, i32) {
+        let cost = self.g_weight * clicks as i32 + self.heuristic.estimate(grid);
+        let tie = match self.tie_break {
+            TieBreak::None => 0,
+            TieBreak::Deeper => clicks as i32,
+            TieBreak::Shallower => -(clicks as i32),
+        };
+
+        (-cost, tie)
+    }
+
+    fn check_limits(&self, stats: &SolverStats, memory: usize) -> Option<Limit> {
+        if let Some(cancel) = &self.cancel {
+            if cancel.is_cancelled() {
+                return Some(Limit::Cancelled);
             }
+        }
 
-            if is_nothing {
-                continue;
+        let limits = &self.limits;
+        if limits
+            .max_nodes
+            .is_some_and(|max| stats.nodes_expanded >= max)
+        {
+            Some(Limit::Nodes)
+        } else if limits.max_time.is_some_and(|max| stats.elapsed >= max) {
+            Some(Limit::Time)
+        } else if limits.max_memory.is_some_and(|max| memory >= max) {
+            Some(Limit::Memory)
+        } else {
+            None
+        }
+    }
+
+    fn should_skip(&self, func: &ButtonFunction, is_last_clicked: bool) -> bool {
+        if self.skip_nothing {
+            if let ButtonFunction::Nothing = func {
+                return true;
             }
-            grid.set_states(&previous_state);
-            grid.click(x, y);
+        }
 
-            let mut item = SolverItem::new();
-            item.orders.push(Coordinate::new(x, y));
-            item.state = grid.get_states();
+        // A linear click is a fixed XOR, so a second one undoes it
+        self.skip_repeated_linear && is_last_clicked && func.is_linear()
+    }
+}
 
-            let cost = calculate_cost(&item, &target_state);
-            pq.push(item, -1 * cost);
+impl Default for SolverConfig {
+    fn default() -> SolverConfig {
+        SolverConfig::new()
+    }
+}
+
+// Approximate bytes used by the search: the node arena, the visited map
+// and the priority queue
+fn estimate_memory(nodes: usize, visited: usize, queued: usize, key_bytes: usize) -> usize {
+    let node_bytes = nodes * (size_of::<SolverNode>() + key_bytes);
+    let visited_bytes = visited * (size_of::<StateKey>() + size_of::<usize>() + key_bytes + 16);
+    let queued_bytes = queued * (size_of::<usize>() + size_of::<(i32, i32)>() + 16);
+
+    node_bytes + visited_bytes + queued_bytes
+}
+
+/// Fast greedy search, the solution is not always the shortest
+pub fn run_astar(level: &ToggleLevel) -> Result {
+    run_astar_with(level, &SolverConfig::new()).result
+}
+
+/// Search that returns a minimum-click solution
+pub fn run_optimal(level: &ToggleLevel) -> Result {
+    run_astar_with(level, &SolverConfig::optimal(level)).result
+}
+
+/// Search driven by `config`, returning the statistics with the result
+pub fn run_astar_with(level: &ToggleLevel, config: &SolverConfig) -> SolverOutput {
+    run_astar_observed(level, config, &mut |_: &SolverStats| {})
+}
 
-            visited.insert(grid.get_states().to_base64(), true);
+/// Search driven by `config` that starts from `states` instead of the level's states
+pub fn run_astar_from(
+    level: &ToggleLevel,
+    states: &BitArray,
+    config: &SolverConfig,
+) -> SolverOutput {
+    let mut grid = BitGrid::from_level(level);
+    grid.set_states(states);
+    search(grid, config, &mut |_: &SolverStats| {})
+}
+
+/// Best-first search driven by `config`. A state is searched again only
+/// when it is reached with fewer clicks than before, so an admissible and
+/// consistent heuristic with g_weight 1 yields a minimum-click solution.
+pub fn run_astar_observed(
+    level: &ToggleLevel,
+    config: &SolverConfig,
+    observer: &mut dyn SearchObserver,
+) -> SolverOutput {
+    search(BitGrid::from_level(level), config, observer)
+}
+
+fn search(
+    mut grid: BitGrid,
+    config: &SolverConfig,
+    observer: &mut dyn SearchObserver,
+) -> SolverOutput {
+    let num_cells = (grid.width() * grid.height()) as usize;
+    let start_key = grid.to_key();
+    let start_diff = grid.remaining() as i32;
+
+    let mut nodes = vec![SolverNode {
+        key: start_key.clone(),
+        parent: NO_PARENT,
+        coord: Coordinate::new(-1, -1),
+        clicks: 0,
+    }];
+
+    // Node of every state seen, holding the fewest clicks found so far
+    let mut visited: HashMap<StateKey, usize> = HashMap::new();
+    visited.insert(start_key, 0);
+
+    let mut pq = PriorityQueue::new();
+    pq.push(0, config.priority(&grid, 0));
+
+    let key_bytes = match num_cells {
+        0..=128 => 0,
+        _ => num_cells.div_ceil(64) * 8,
+    };
+
+    let mut closest_diff = start_diff;
+    let mut closest_index = 0;
+
+    let mut stats = SolverStats::new();
+    stats.nodes_generated = 1;
+    stats.peak_open = 1;
+
+    let started = Instant::now();
+    while let Some((index, _)) = pq.pop() {
+        stats.visited = visited.len();
+        stats.elapsed = started.elapsed();
+
+        let memory = estimate_memory(nodes.len(), visited.len(), pq.len(), key_bytes);
+        if let Some(reason) = config.check_limits(&stats, memory) {
+            let partial = Partial {
+                reason,
+                orders: build_orders(&nodes, closest_index),
+                state: BitArray::from_key(&nodes[closest_index].key, num_cells),
+                diff: closest_diff,
+                stats,
+            };
+            return SolverOutput {
+                result: Result::Aborted(partial),
+                stats,
+            };
         }
-    }
+        stats.nodes_expanded += 1;
 
-    let stopwatch = Stopwatch::start_new();
-    let mut iterations = 0;
-    while !pq.is_empty() {
-        iterations += 1;
+        grid.set_key(&nodes[index].key);
+        let state_words = grid.words().to_vec();
 
-        let item = pq.pop().unwrap().0;
-        let item_func = grid.at(item.coord.x, item.coord.y).unwrap().func;
+        let diff = grid.remaining() as i32;
+        if diff < closest_diff {
+            closest_diff = diff;
+            closest_index = index;
+        }
 
-        let diff = calculate_diff(&item.state, &target_state);
-        if iterations % 10000 == 0 {
-            println!(
-                "?{},{},{},{}",
-                iterations,
-                diff,
-                pq.len(),
-                item.orders.len()
-            );
+        if config.progress_interval > 0
+            && stats
+                .nodes_expanded
+                .is_multiple_of(config.progress_interval)
+        {
+            observer.on_progress(&stats);
         }
 
         if diff == 0 {
-            println!(
-                "!{},{},{}",
-                iterations,
-                item.orders.len(),
-                stopwatch.elapsed_ms()
-            );
-            result = Result::Success(item.orders);
-            break;
+            let orders = build_orders(&nodes, index);
+            stats.solution_length = Some(orders.len());
+            return SolverOutput {
+                result: Result::Success(orders),
+                stats,
+            };
         }
 
-        previous_state = item.state;
+        let last_coord = nodes[index].coord;
+        let clicks = nodes[index].clicks + 1;
         for y in 0..grid.height() {
             for x in 0..grid.width() {
-                let mut should_skip: bool = false;
+                let func = grid.func(x, y);
+                let is_last_clicked = x == last_coord.x && y == last_coord.y;
+                if config.should_skip(func, is_last_clicked) {
+                    continue;
+                }
 
-                match grid.at(x, y).unwrap().func {
-                    ButtonFunction::Nothing => {
-                        should_skip = true;
+                grid.set_words(&state_words);
+                grid.click(x, y);
+                stats.nodes_generated += 1;
+
+                let new_key = grid.to_key();
+
+                let new_index = match visited.get(&new_key) {
+                    Some(&known) if nodes[known].clicks <= clicks => continue,
+                    Some(&known) => known,
+                    None => {
+                        nodes.push(SolverNode {
+                            key: new_key.clone(),
+                            parent: NO_PARENT,
+                            coord: Coordinate::new(-1, -1),
+                            clicks,
+                        });
+                        visited.insert(new_key, nodes.len() - 1);
+                        nodes.len() - 1
                     }
-                    _ => {}
-                }
+                };
 
-                if x == item.coord.x && y == item.coord.y {
-                    let func = grid.at(x, y).unwrap().func;
+                let node = &mut nodes[new_index];
+                node.parent = index;
+                node.coord = Coordinate::new(x, y);
+                node.clicks = clicks;
 
-                    if is_arrow(item_func) && is_arrow(func) {
-                        should_skip = true;
-                    }
-                }
+                pq.push(new_index, config.priority(&grid, clicks));
+            }
+        }
+        stats.peak_open = stats.peak_open.max(pq.len());
+    }
 
-                if should_skip {
-                    continue;
-                }
+    stats.visited = visited.len();
+    stats.elapsed = started.elapsed();
+    SolverOutput {
+        result: Result::Fail,
+        stats,
+    }
+}
 
-                grid.set_states(&previous_state);
-                grid.click(x, y);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::core::Topology;
+    use crate::toggle::testing::{random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::Rng;
+
+    const ALL_SUBTYPES: [u8; 26] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25,
+    ];
+
+    fn reaches_goal(level: &ToggleLevel, orders: &[Coordinate]) -> bool {
+        let mut grid = Grid::from_level(level);
+        for order in orders.iter() {
+            grid.click(order.x, order.y);
+        }
+        level.goal().is_reached(&grid.get_states())
+    }
 
-                let states_base64 = grid.get_states().to_base64();
-                if visited.contains_key(&states_base64) == false {
-                    let mut new_item = SolverItem::new();
-                    new_item.orders.extend_from_slice(&item.orders[..]);
-                    new_item.orders.push(Coordinate::new(x, y));
-                    new_item.state = grid.get_states();
+    fn random_levels(rng: &mut Rng) -> Vec<ToggleLevel> {
+        let mut result = Vec::new();
+        for (width, height) in [(1, 3), (2, 2), (3, 2), (2, 3), (3, 3)] {
+            for i in 0..30 {
+                let mut level = random_level(width, height, &ALL_SUBTYPES, rng);
+                if i % 2 == 1 {
+                    random_goal(&mut level, rng);
+                }
+                if i % 3 == 2 {
+                    level.topology = Topology::Torus;
+                }
+                result.push(level);
+            }
+        }
+        result
+    }
 
-                    let cost = calculate_cost(&new_item, &target_state);
-                    pq.push(new_item, -1 * cost);
+    #[test]
+    fn optimal_matches_breadth_first_search() {
+        let mut rng = Rng::new(17);
+        for level in random_levels(&mut rng) {
+            match (run_optimal(&level), shortest_solution(&level)) {
+                (Result::Success(orders), Some(clicks)) => {
+                    assert_eq!(orders.len(), clicks, "{:?}", level.subtypes);
+                    assert!(reaches_goal(&level, &orders));
+                }
+                (Result::Fail, None) => {}
+                (_, clicks) => panic!("{:?} has shortest solution {:?}", level.subtypes, clicks),
+            }
+        }
+    }
 
-                    visited.insert(states_base64, true);
+    #[test]
+    fn fast_search_finds_a_solution_when_one_exists() {
+        let mut rng = Rng::new(19);
+        for level in random_levels(&mut rng) {
+            match (run_astar(&level), shortest_solution(&level)) {
+                (Result::Success(orders), Some(clicks)) => {
+                    assert!(orders.len() >= clicks);
+                    assert!(reaches_goal(&level, &orders));
                 }
+                (Result::Fail, None) => {}
+                (_, clicks) => panic!("{:?} has shortest solution {:?}", level.subtypes, clicks),
             }
         }
     }
 
-    result
+    #[test]
+    fn node_limit_aborts_the_search() {
+        let mut rng = Rng::new(23);
+        let level = random_level(5, 5, &[14, 15, 16, 17], &mut rng);
+        let mut config = SolverConfig::optimal(&level);
+        config.limits.max_nodes = Some(10);
+
+        match run_astar_with(&level, &config).result {
+            Result::Aborted(partial) => assert_eq!(partial.reason, Limit::Nodes),
+            Result::Success(orders) => assert!(orders.len() <= 10),
+            Result::Fail => panic!("stopped without reaching the limit"),
+        }
+    }
 }
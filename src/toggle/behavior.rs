@@ -0,0 +1,505 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::bitgrid::BitGrid;
+use super::core::{ButtonFunction, Coordinate, Grid, OneArrow, Topology, TwoArrow};
+use super::utils::{BitArray, Rng};
+
+/// What a click on a button does. The built-in functions implement it through
+/// ButtonFunction, other crates can add their own types to a Registry.
+///
+/// The solvers derive the effect of a click by probing it on single-cell states,
+/// so a click has to copy or clear cells and then toggle a fixed set of cells.
+/// A click may not depend on the state of the board in any other way,
+/// which `register` checks on a few sample boards.
+pub trait ButtonBehavior: fmt::Debug + Send + Sync {
+    /// Clicks the button at (x, y)
+    fn apply(&self, grid: &mut Grid, x: i32, y: i32);
+
+    /// True when a click is a plain XOR of a fixed mask, independent of the current states
+    fn is_linear(&self) -> bool;
+
+    /// Function that undoes a click when clicked at the same position of the same board,
+    /// None if the click loses states there. `grid` has this button at (x, y).
+    fn inverse(&self, grid: &Grid, x: i32, y: i32) -> Option<ButtonFunction> {
+        invert(grid, x, y)
+    }
+
+    /// Cells whose state may change when the button at (x, y) is clicked
+    fn affected_cells(&self, grid: &Grid, x: i32, y: i32) -> Vec<Coordinate>;
+}
+
+#[derive(Debug)]
+pub enum BehaviorError {
+    // A click on a sample board is not a copy or clear of cells followed by a toggle
+    NotAffine {
+        width: i32,
+        height: i32,
+        topology: Topology,
+        x: i32,
+        y: i32,
+    },
+    // Subtype of Nothing or of a built-in button
+    Reserved {
+        subtype: u8,
+    },
+}
+
+impl fmt::Display for BehaviorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BehaviorError::NotAffine {
+                width,
+                height,
+                topology,
+                x,
+                y,
+            } => write!(
+                f,
+                "click at ({}, {}) on a {}x{} {:?} board is not a copy followed by a toggle",
+                x, y, width, height, topology
+            ),
+            BehaviorError::Reserved { subtype } => {
+                write!(f, "subtype {} is reserved for a built-in button", subtype)
+            }
+        }
+    }
+}
+
+impl Error for BehaviorError {}
+
+// Subtypes of Nothing and the built-in buttons, which can not be replaced
+const RESERVED_SUBTYPES: u8 = 25;
+
+// Boards a behavior is clicked on before it gets a subtype
+const SAMPLE_SIZES: [(i32, i32); 4] = [(1, 1), (3, 2), (4, 4), (5, 3)];
+// Random states every sample click is compared on
+const SAMPLE_STATES: usize = 8;
+
+/// Clicks `func` on every cell of a few sample boards and checks that the
+/// solvers' precomputed clicks match it
+pub fn validate(func: &ButtonFunction) -> Result<(), BehaviorError> {
+    let mut rng = Rng::new(0);
+
+    for (width, height) in SAMPLE_SIZES {
+        for topology in [Topology::Bounded, Topology::Torus, Topology::Cylinder] {
+            let not_affine = |coord: Coordinate| BehaviorError::NotAffine {
+                width,
+                height,
+                topology,
+                x: coord.x,
+                y: coord.y,
+            };
+
+            let mut grid = Grid::new(width, height);
+            grid.set_topology(topology);
+            for y in 0..height {
+                for x in 0..width {
+                    grid.at_mut(x, y).unwrap().func = func.clone();
+                }
+            }
+            let mut bit_grid = BitGrid::try_from_grid(&grid).map_err(not_affine)?;
+
+            let num_cells = (width * height) as usize;
+            for y in 0..height {
+                for x in 0..width {
+                    for _ in 0..SAMPLE_STATES {
+                        let mut states = BitArray::new(num_cells);
+                        for cell in 0..num_cells {
+                            states.set(cell, rng.chance(0.5));
+                        }
+
+                        grid.set_states(&states);
+                        bit_grid.set_states(&states);
+                        grid.click(x, y);
+                        bit_grid.click(x, y);
+                        if grid.get_states() != bit_grid.get_states() {
+                            return Err(not_affine(Coordinate::new(x, y)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Inverse of the button that `grid` has at (x, y): the button itself if
+/// two clicks cancel out, otherwise a button that unclicks it on this board.
+/// None if the click loses states.
+pub fn invert(grid: &Grid, x: i32, y: i32) -> Option<ButtonFunction> {
+    let func = grid.at(x, y)?.func.clone();
+    let bit_grid = BitGrid::from_grid(grid);
+    let num_cells = (grid.width() * grid.height()) as usize;
+
+    // The click is a bijection if it only permutes cells before toggling
+    let mut is_source = vec![false; num_cells];
+    let mut is_destination = vec![false; num_cells];
+    for &(destination, source) in bit_grid.action(x, y).moves() {
+        let source = source?;
+        if is_source[source] {
+            return None;
+        }
+        is_source[source] = true;
+        is_destination[destination] = true;
+    }
+    if is_source != is_destination {
+        return None;
+    }
+
+    // Clicks are affine, so the zero state and single-cell states decide it
+    let mut probe = bit_grid.clone();
+    let is_involution = (0..=num_cells).all(|cell| {
+        let mut states = BitArray::new(num_cells);
+        if cell < num_cells {
+            states.set(cell, true);
+        }
+        probe.set_states(&states);
+        probe.click(x, y);
+        probe.click(x, y);
+        probe.get_states() == states
+    });
+    if is_involution {
+        return Some(func);
+    }
+
+    Some(ButtonFunction::Custom(Arc::new(Unclick { func, bit_grid })))
+}
+
+// Undoes the clicks of `func` on the board it was inverted on
+struct Unclick {
+    func: ButtonFunction,
+    bit_grid: BitGrid,
+}
+
+impl fmt::Debug for Unclick {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Unclick").field(&self.func).finish()
+    }
+}
+
+impl ButtonBehavior for Unclick {
+    fn apply(&self, grid: &mut Grid, x: i32, y: i32) {
+        let mut bit_grid = self.bit_grid.clone();
+        bit_grid.set_states(&grid.get_states());
+        bit_grid.unclick(x, y);
+        grid.set_states(&bit_grid.get_states());
+    }
+
+    fn is_linear(&self) -> bool {
+        self.func.is_linear()
+    }
+
+    fn inverse(&self, _grid: &Grid, _x: i32, _y: i32) -> Option<ButtonFunction> {
+        Some(self.func.clone())
+    }
+
+    fn affected_cells(&self, grid: &Grid, x: i32, y: i32) -> Vec<Coordinate> {
+        self.func.affected_cells(grid, x, y)
+    }
+}
+
+//
+// Registry
+//
+// Maps the subtype bytes of a level code to button functions.
+//
+#[derive(Debug, Clone)]
+pub struct Registry {
+    functions: Vec<ButtonFunction>,
+}
+
+impl Registry {
+    /// The built-in button types, subtypes 1 to 25
+    pub fn new() -> Registry {
+        let mut functions = vec![ButtonFunction::Nothing; 256];
+
+        let built_in = [
+            // One Arrow Linear
+            ButtonFunction::OneArrow(OneArrow::Up),
+            ButtonFunction::OneArrow(OneArrow::Down),
+            ButtonFunction::OneArrow(OneArrow::Left),
+            ButtonFunction::OneArrow(OneArrow::Right),
+            // One Arrow Diagonal
+            ButtonFunction::OneArrow(OneArrow::LeftUp),
+            ButtonFunction::OneArrow(OneArrow::RightUp),
+            ButtonFunction::OneArrow(OneArrow::LeftDown),
+            ButtonFunction::OneArrow(OneArrow::RightDown),
+            // Two Arrow Linear
+            ButtonFunction::TwoArrow(TwoArrow::BothHorizontal),
+            ButtonFunction::TwoArrow(TwoArrow::BothVertical),
+            // Four Arrow
+            ButtonFunction::FourArrow,
+            // Two Arrow Diagonal
+            ButtonFunction::TwoArrow(TwoArrow::LeftUpRightDown),
+            ButtonFunction::TwoArrow(TwoArrow::LeftDownRightUp),
+            // Rotate
+            ButtonFunction::Rotate(true),  // Clockwise
+            ButtonFunction::Rotate(false), // Counter-Clockwise
+            // Symmetry
+            ButtonFunction::Symmetry(true),  // Horizontal
+            ButtonFunction::Symmetry(false), // Vertical
+            // Shift
+            ButtonFunction::Shift(false), // Left
+            ButtonFunction::Shift(true),  // Right
+            // Around Eight
+            ButtonFunction::AroundEight,
+            // Knight
+            ButtonFunction::Knight,
+            // Column Shift
+            ButtonFunction::ColumnShift(true),  // Up
+            ButtonFunction::ColumnShift(false), // Down
+            // Cross Diagonal
+            ButtonFunction::CrossDiagonal,
+            // Block Flip
+            ButtonFunction::BlockFlip,
+        ];
+        for (i, func) in built_in.into_iter().enumerate() {
+            functions[i + 1] = func;
+        }
+
+        Registry { functions }
+    }
+
+    /// Makes `subtype` click with `behavior`, replacing the custom function it had.
+    /// Fails for the subtypes 0 to 25 of the built-in buttons, or if `validate`
+    /// rejects the behavior.
+    pub fn register(
+        &mut self,
+        subtype: u8,
+        behavior: impl ButtonBehavior + 'static,
+    ) -> Result<(), BehaviorError> {
+        if subtype <= RESERVED_SUBTYPES {
+            return Err(BehaviorError::Reserved { subtype });
+        }
+        let func = ButtonFunction::Custom(Arc::new(behavior));
+        validate(&func)?;
+
+        self.functions[subtype as usize] = func;
+        Ok(())
+    }
+
+    /// Function of a subtype, Nothing for subtypes without one
+    pub fn function(&self, subtype: u8) -> ButtonFunction {
+        self.functions[subtype as usize].clone()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+fn global_lock() -> &'static RwLock<Arc<Registry>> {
+    static GLOBAL: OnceLock<RwLock<Arc<Registry>>> = OnceLock::new();
+    GLOBAL.get_or_init(|| RwLock::new(Arc::new(Registry::new())))
+}
+
+/// Registry that `Grid::from_level` reads subtypes with
+pub fn global() -> Arc<Registry> {
+    global_lock().read().unwrap().clone()
+}
+
+/// Adds a button type to the global registry, so every solver sees it.
+/// Grids built before the call keep their functions.
+/// Fails for the subtypes 0 to 25 of the built-in buttons, or if `validate`
+/// rejects the behavior.
+pub fn register(subtype: u8, behavior: impl ButtonBehavior + 'static) -> Result<(), BehaviorError> {
+    if subtype <= RESERVED_SUBTYPES {
+        return Err(BehaviorError::Reserved { subtype });
+    }
+    // Checked before taking the lock, the behavior may read the registry
+    let func = ButtonFunction::Custom(Arc::new(behavior));
+    validate(&func)?;
+
+    let mut registry = global_lock().write().unwrap();
+    Arc::make_mut(&mut registry).functions[subtype as usize] = func;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Toggles itself only when its right neighbor is on as well
+    #[derive(Debug)]
+    struct AndToggle;
+
+    impl ButtonBehavior for AndToggle {
+        fn apply(&self, grid: &mut Grid, x: i32, y: i32) {
+            let is_right_on = grid
+                .neighbor(x, y, 1, 0)
+                .is_some_and(|right| grid.at(right.x, right.y).unwrap().is_on);
+            let btn = grid.at_mut(x, y).unwrap();
+            if btn.is_on && is_right_on {
+                btn.is_on = false;
+            }
+        }
+
+        fn is_linear(&self) -> bool {
+            false
+        }
+
+        fn affected_cells(&self, _grid: &Grid, x: i32, y: i32) -> Vec<Coordinate> {
+            vec![Coordinate::new(x, y)]
+        }
+    }
+
+    // Turns itself on when its right neighbor is on, merging two cells into one
+    #[derive(Debug)]
+    struct Merge;
+
+    impl ButtonBehavior for Merge {
+        fn apply(&self, grid: &mut Grid, x: i32, y: i32) {
+            if let Some(right) = grid.neighbor(x, y, 1, 0) {
+                if grid.at(right.x, right.y).unwrap().is_on {
+                    grid.at_mut(x, y).unwrap().is_on = true;
+                }
+            }
+        }
+
+        fn is_linear(&self) -> bool {
+            false
+        }
+
+        fn affected_cells(&self, grid: &Grid, x: i32, y: i32) -> Vec<Coordinate> {
+            let mut result = vec![Coordinate::new(x, y)];
+            result.extend(grid.neighbor(x, y, 1, 0));
+            result
+        }
+    }
+
+    // Toggles itself and the cell to its left
+    #[derive(Debug)]
+    struct LeftPair;
+
+    impl ButtonBehavior for LeftPair {
+        fn apply(&self, grid: &mut Grid, x: i32, y: i32) {
+            for cell in self.affected_cells(grid, x, y) {
+                let btn = grid.at_mut(cell.x, cell.y).unwrap();
+                btn.is_on = !btn.is_on;
+            }
+        }
+
+        fn is_linear(&self) -> bool {
+            true
+        }
+
+        fn affected_cells(&self, grid: &Grid, x: i32, y: i32) -> Vec<Coordinate> {
+            let mut result = vec![Coordinate::new(x, y)];
+            result.extend(grid.neighbor(x, y, -1, 0));
+            result
+        }
+    }
+
+    #[test]
+    fn built_in_functions_are_valid() {
+        let registry = Registry::new();
+        for subtype in 1..=25 {
+            let func = registry.function(subtype);
+            assert!(validate(&func).is_ok(), "{:?}", func);
+        }
+    }
+
+    #[test]
+    fn affine_behaviors_register() {
+        let mut registry = Registry::new();
+        assert!(registry.register(200, LeftPair).is_ok());
+        assert!(matches!(registry.function(200), ButtonFunction::Custom(_)));
+    }
+
+    #[test]
+    fn other_behaviors_are_rejected() {
+        let mut registry = Registry::new();
+
+        assert!(matches!(
+            registry.register(200, AndToggle),
+            Err(BehaviorError::NotAffine { .. })
+        ));
+        assert!(matches!(
+            registry.register(201, Merge),
+            Err(BehaviorError::NotAffine { .. })
+        ));
+
+        assert!(matches!(registry.function(200), ButtonFunction::Nothing));
+        assert!(matches!(registry.function(201), ButtonFunction::Nothing));
+    }
+
+    #[test]
+    fn built_in_subtypes_are_reserved() {
+        let mut registry = Registry::new();
+        for subtype in [0, 11, 25] {
+            assert!(matches!(
+                registry.register(subtype, LeftPair),
+                Err(BehaviorError::Reserved { .. })
+            ));
+            assert!(matches!(
+                register(subtype, LeftPair),
+                Err(BehaviorError::Reserved { .. })
+            ));
+        }
+        assert!(matches!(registry.function(11), ButtonFunction::FourArrow));
+        assert!(registry.register(26, LeftPair).is_ok());
+    }
+
+    #[test]
+    fn inverse_undoes_the_click() {
+        let registry = Registry::new();
+        let mut rng = Rng::new(0);
+        let (mut inverted, mut unclicked, mut lost) = (0, 0, 0);
+        for subtype in 1..=25 {
+            let func = registry.function(subtype);
+            for (width, height) in [(1, 1), (3, 3), (4, 3), (5, 5)] {
+                for topology in [Topology::Bounded, Topology::Torus, Topology::Cylinder] {
+                    let mut grid = Grid::new(width, height);
+                    grid.set_topology(topology);
+                    for y in 0..height {
+                        for x in 0..width {
+                            grid.at_mut(x, y).unwrap().func = func.clone();
+                        }
+                    }
+
+                    for y in 0..height {
+                        for x in 0..width {
+                            let inverse = match func.inverse(&grid, x, y) {
+                                Some(inverse) => inverse,
+                                None => {
+                                    lost += 1;
+                                    continue;
+                                }
+                            };
+                            inverted += 1;
+                            if matches!(inverse, ButtonFunction::Custom(_)) {
+                                unclicked += 1;
+                            }
+
+                            let mut states = BitArray::new((width * height) as usize);
+                            for cell in 0..states.len() {
+                                states.set(cell, rng.chance(0.5));
+                            }
+                            grid.set_states(&states);
+                            grid.click(x, y);
+                            grid.at_mut(x, y).unwrap().func = inverse;
+                            grid.click(x, y);
+                            grid.at_mut(x, y).unwrap().func = func.clone();
+                            assert_eq!(grid.get_states(), states, "{:?} at {},{}", func, x, y);
+                        }
+                    }
+                }
+            }
+        }
+        assert!(inverted > 0 && unclicked > 0 && lost > 0);
+    }
+
+    #[test]
+    fn shifts_that_lose_lights_have_no_inverse() {
+        let mut grid = Grid::new(3, 1);
+        for x in 0..3 {
+            grid.at_mut(x, 0).unwrap().func = ButtonFunction::Shift(false);
+        }
+        assert!(ButtonFunction::Shift(false).inverse(&grid, 1, 0).is_none());
+    }
+}
@@ -0,0 +1,408 @@
+use super::core::{ButtonFunction, Coordinate, Goal, Grid, ToggleLevel};
+use super::utils::{BitArray, StateKey};
+
+//
+// Click Action
+//
+// Every button function is affine over GF(2): a click copies some cells
+// into others (or clears them) and then toggles a fixed mask.
+//
+#[derive(Clone)]
+pub struct Action {
+    mask: Vec<u64>,
+    // (destination, source) pairs, a missing source clears the destination
+    moves: Vec<(usize, Option<usize>)>,
+}
+
+impl Action {
+    /// True if the click toggles the cell at column-major index `cell`
+    pub fn toggles(&self, cell: usize) -> bool {
+        get_bit(&self.mask, cell)
+    }
+
+    /// (destination, source) pairs of column-major cell indices, the cells
+    /// a click copies before toggling. A missing source clears the destination.
+    pub fn moves(&self) -> &[(usize, Option<usize>)] {
+        &self.moves
+    }
+}
+
+//
+// Bit Grid
+//
+// States packed into u64 words in the same column-major order as
+// Grid::get_states, with the effect of every button precomputed.
+// The goal is kept in the same layout, every light off unless set.
+//
+#[derive(Clone)]
+pub struct BitGrid {
+    width: i32,
+    height: i32,
+    num_cells: usize,
+    words: Vec<u64>,
+    target: Vec<u64>,
+    care: Vec<u64>,
+    funcs: Vec<ButtonFunction>,
+    actions: Vec<Action>,
+    moved: Vec<bool>,
+}
+
+pub(super) fn word_count(num_cells: usize) -> usize {
+    num_cells.div_ceil(64).max(1)
+}
+
+pub(super) fn get_bit(words: &[u64], index: usize) -> bool {
+    (words[index / 64] >> (index % 64)) & 1 != 0
+}
+
+pub(super) fn set_bit(words: &mut [u64], index: usize, is_on: bool) {
+    if is_on {
+        words[index / 64] |= 1 << (index % 64);
+    } else {
+        words[index / 64] &= !(1 << (index % 64));
+    }
+}
+
+pub(super) fn to_words(b: &BitArray, num_cells: usize) -> Vec<u64> {
+    let mut result = vec![0; word_count(num_cells)];
+    for i in 0..num_cells {
+        set_bit(&mut result, i, b.get(i));
+    }
+    result
+}
+
+impl BitGrid {
+    pub fn from_level(level: &ToggleLevel) -> BitGrid {
+        let mut result = BitGrid::from_grid(&Grid::from_level(level));
+        if level.goal.is_some() || level.voids.is_some() {
+            result.set_goal(&level.goal());
+        }
+        result
+    }
+
+    /// Panics if a custom button does not click the way ButtonBehavior asks,
+    /// which `behavior::register` checks before a behavior gets a subtype
+    pub fn from_grid(grid: &Grid) -> BitGrid {
+        match BitGrid::try_from_grid(grid) {
+            Ok(result) => result,
+            Err(coord) => panic!(
+                "click at ({}, {}) is not a copy followed by a toggle",
+                coord.x, coord.y
+            ),
+        }
+    }
+
+    /// Like `from_grid`, but returns the first button whose click copies
+    /// two cells into one instead of panicking
+    pub fn try_from_grid(grid: &Grid) -> Result<BitGrid, Coordinate> {
+        let width = grid.width();
+        let height = grid.height();
+        let num_cells = width as usize * height as usize;
+
+        let mut probe = Grid::new(width, height);
+        probe.set_topology(grid.topology());
+        probe.set_ray_mode(grid.ray_mode());
+        let mut funcs = Vec::with_capacity(num_cells);
+        for y in 0..height {
+            for x in 0..width {
+                let btn = grid.at(x, y).unwrap();
+                let probe_btn = probe.at_mut(x, y).unwrap();
+                probe_btn.func = btn.func.clone();
+                probe_btn.is_void = btn.is_void;
+                funcs.push(btn.func.clone());
+            }
+        }
+
+        let mut actions = Vec::with_capacity(num_cells);
+        for y in 0..height {
+            for x in 0..width {
+                match BitGrid::probe_action(&mut probe, x, y) {
+                    Some(action) => actions.push(action),
+                    None => return Err(Coordinate::new(x, y)),
+                }
+            }
+        }
+
+        Ok(BitGrid {
+            width,
+            height,
+            num_cells,
+            words: to_words(&grid.get_states(), num_cells),
+            target: vec![0; word_count(num_cells)],
+            care: to_words(&Goal::new(num_cells).care, num_cells),
+            funcs,
+            actions,
+            moved: Vec::new(),
+        })
+    }
+
+    // Derives the action of a button by clicking it on single-cell states.
+    // None if two cells end up in the same one.
+    fn probe_action(probe: &mut Grid, x: i32, y: i32) -> Option<Action> {
+        let height = probe.height();
+        let index_of = |c: &Coordinate| (c.x * height + c.y) as usize;
+
+        probe.set_all_state(false);
+        probe.click(x, y);
+        let mask_states = probe.get_states();
+        let mask = to_words(&mask_states, mask_states.len());
+
+        let mut moves = Vec::new();
+        if probe.at(x, y).unwrap().func.is_linear() {
+            return Some(Action { mask, moves });
+        }
+
+        let affected = probe.affected_cells(x, y);
+        let mut sources: Vec<Option<usize>> = vec![None; affected.len()];
+        for source in affected.iter() {
+            probe.set_all_state(false);
+            probe.at_mut(source.x, source.y).unwrap().is_on = true;
+            probe.click(x, y);
+
+            for (i, target) in affected.iter().enumerate() {
+                let is_on = probe.at(target.x, target.y).unwrap().is_on;
+                if is_on != get_bit(&mask, index_of(target)) {
+                    if sources[i].is_some() {
+                        return None;
+                    }
+                    sources[i] = Some(index_of(source));
+                }
+            }
+        }
+
+        for (target, source) in affected.iter().zip(sources) {
+            let destination = index_of(target);
+            if source != Some(destination) {
+                moves.push((destination, source));
+            }
+        }
+
+        Some(Action { mask, moves })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn func(&self, x: i32, y: i32) -> &ButtonFunction {
+        &self.funcs[(y * self.width + x) as usize]
+    }
+
+    pub(crate) fn action(&self, x: i32, y: i32) -> &Action {
+        &self.actions[(y * self.width + x) as usize]
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> bool {
+        get_bit(&self.words, (x * self.height + y) as usize)
+    }
+
+    pub fn click(&mut self, x: i32, y: i32) {
+        let action = &self.actions[(y * self.width + x) as usize];
+
+        self.moved.clear();
+        for &(_, source) in action.moves.iter() {
+            self.moved
+                .push(source.is_some_and(|s| get_bit(&self.words, s)));
+        }
+        for (&(destination, _), &is_on) in action.moves.iter().zip(self.moved.iter()) {
+            set_bit(&mut self.words, destination, is_on);
+        }
+
+        for (word, mask) in self.words.iter_mut().zip(action.mask.iter()) {
+            *word ^= *mask;
+        }
+    }
+
+    /// Replaces the states with ones that `click(x, y)` turns into the current states.
+    /// Cells the click overwrites without reading are chosen to be off.
+    /// Returns false and keeps the states if the click can never produce them.
+    pub fn unclick(&mut self, x: i32, y: i32) -> bool {
+        let action = &self.actions[(y * self.width + x) as usize];
+
+        let mut target = self.words.clone();
+        for (word, mask) in target.iter_mut().zip(action.mask.iter()) {
+            *word ^= *mask;
+        }
+
+        // Cells the click does not overwrite already had their current state
+        let mut known: Vec<Option<bool>> = (0..self.num_cells)
+            .map(|i| Some(get_bit(&target, i)))
+            .collect();
+        for &(destination, _) in action.moves.iter() {
+            known[destination] = None;
+        }
+
+        for &(destination, source) in action.moves.iter() {
+            let is_on = get_bit(&target, destination);
+            match source {
+                None if is_on => return false,
+                None => {}
+                Some(source) => match known[source] {
+                    Some(state) if state != is_on => return false,
+                    _ => known[source] = Some(is_on),
+                },
+            }
+        }
+
+        for (i, state) in known.into_iter().enumerate() {
+            set_bit(&mut self.words, i, state.unwrap_or(false));
+        }
+        true
+    }
+
+    pub(crate) fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub(crate) fn set_words(&mut self, words: &[u64]) {
+        self.words.copy_from_slice(words);
+    }
+
+    pub fn set_goal(&mut self, goal: &Goal) {
+        self.target = to_words(&goal.target, self.num_cells);
+        self.care = to_words(&goal.care, self.num_cells);
+    }
+
+    /// Number of cared cells that differ from the goal
+    pub fn remaining(&self) -> u32 {
+        self.words
+            .iter()
+            .zip(self.target.iter().zip(self.care.iter()))
+            .map(|(word, (target, care))| ((word ^ target) & care).count_ones())
+            .sum()
+    }
+
+    pub fn is_reached(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn get_states(&self) -> BitArray {
+        let mut result = BitArray::new(self.num_cells);
+        for i in 0..self.num_cells {
+            result.set(i, get_bit(&self.words, i));
+        }
+        result
+    }
+
+    pub fn set_states(&mut self, b: &BitArray) {
+        self.words = to_words(b, self.num_cells);
+    }
+
+    pub fn set_all_state(&mut self, state: bool) {
+        for i in 0..self.num_cells {
+            set_bit(&mut self.words, i, state);
+        }
+    }
+
+    pub(crate) fn to_key(&self) -> StateKey {
+        if self.num_cells <= 128 {
+            let high = self.words.get(1).copied().unwrap_or(0);
+            StateKey::Small(u128::from(self.words[0]) | (u128::from(high) << 64))
+        } else {
+            StateKey::Large(self.words.clone().into_boxed_slice())
+        }
+    }
+
+    pub(crate) fn set_key(&mut self, key: &StateKey) {
+        match key {
+            StateKey::Small(value) => {
+                self.words[0] = *value as u64;
+                if let Some(high) = self.words.get_mut(1) {
+                    *high = (*value >> 64) as u64;
+                }
+            }
+            StateKey::Large(words) => self.words.copy_from_slice(words),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::core::{RayMode, Topology};
+    use crate::toggle::utils::Rng;
+
+    fn random_level(width: u32, height: u32, rng: &mut Rng) -> ToggleLevel {
+        let mut level = ToggleLevel::new();
+        level.width = width;
+        level.height = height;
+        level.subtypes = (0..level.num_cells())
+            .map(|_| rng.below(26) as u8)
+            .collect();
+        level.states = BitArray::new(level.num_cells());
+        for cell in 0..level.num_cells() {
+            level.states.set(cell, rng.chance(0.5));
+        }
+        level
+    }
+
+    // Clicks the same buttons on a Grid and a BitGrid and compares the states,
+    // checking that no light ends up in a void
+    fn assert_same_clicks(level: &ToggleLevel, rng: &mut Rng) {
+        let mut grid = Grid::from_level(level);
+        let mut bit_grid = BitGrid::from_level(level);
+        assert_eq!(grid.get_states(), bit_grid.get_states());
+
+        for _ in 0..30 {
+            let x = rng.below(level.width as usize) as i32;
+            let y = rng.below(level.height as usize) as i32;
+            grid.click(x, y);
+            bit_grid.click(x, y);
+            assert_eq!(
+                grid.get_states(),
+                bit_grid.get_states(),
+                "{:?} at ({}, {})",
+                grid.at(x, y).unwrap().func,
+                x,
+                y
+            );
+
+            let states = grid.get_states();
+            assert!((0..level.num_cells()).all(|cell| !level.is_void(cell) || !states.get(cell)));
+        }
+    }
+
+    #[test]
+    fn clicks_match_grid() {
+        let mut rng = Rng::new(7);
+        for (width, height) in [(1, 1), (2, 3), (4, 4), (5, 3), (9, 2)] {
+            for _ in 0..20 {
+                let level = random_level(width, height, &mut rng);
+                assert_same_clicks(&level, &mut rng);
+            }
+        }
+    }
+
+    #[test]
+    fn clicks_match_grid_on_wrapped_and_shaped_boards() {
+        let mut rng = Rng::new(11);
+        for topology in [Topology::Torus, Topology::Cylinder] {
+            for ray_mode in [RayMode::Block, RayMode::Skip] {
+                for _ in 0..20 {
+                    let mut level = random_level(5, 4, &mut rng);
+                    level.topology = topology;
+                    level.ray_mode = ray_mode;
+
+                    let mut voids = BitArray::new(level.num_cells());
+                    for cell in 0..level.num_cells() {
+                        voids.set(cell, rng.chance(0.2));
+                    }
+                    level.voids = Some(voids);
+
+                    assert_same_clicks(&level, &mut rng);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_boards_use_several_words() {
+        let mut rng = Rng::new(3);
+        let level = random_level(20, 20, &mut rng);
+        assert_same_clicks(&level, &mut rng);
+    }
+}
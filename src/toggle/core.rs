@@ -1,10 +1,13 @@
 
+use std::sync::Arc;
+
+use super::behavior::{self, ButtonBehavior, Registry};
 use super::utils::BitArray;
 
 //
 // Button Function
 //
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum ButtonFunction {
     Nothing,
     OneArrow(OneArrow),
@@ -14,6 +17,160 @@ pub enum ButtonFunction {
     Symmetry(bool),
     Shift(bool),
     AroundEight,
+    Knight,
+    /// Rotates the whole column, up if true
+    ColumnShift(bool),
+    CrossDiagonal,
+    BlockFlip,
+    /// Button type added through a Registry
+    Custom(Arc<dyn ButtonBehavior>),
+}
+
+impl ButtonFunction {
+    /// True when a click is a plain XOR of a fixed mask, independent of the current states
+    pub fn is_linear(&self) -> bool {
+        match self {
+            ButtonFunction::Nothing
+            | ButtonFunction::OneArrow(_)
+            | ButtonFunction::TwoArrow(_)
+            | ButtonFunction::FourArrow
+            | ButtonFunction::AroundEight
+            | ButtonFunction::Knight
+            | ButtonFunction::CrossDiagonal => true,
+            ButtonFunction::Rotate(_)
+            | ButtonFunction::Symmetry(_)
+            | ButtonFunction::Shift(_)
+            | ButtonFunction::ColumnShift(_)
+            | ButtonFunction::BlockFlip => false,
+            ButtonFunction::Custom(behavior) => behavior.is_linear(),
+        }
+    }
+}
+
+impl ButtonBehavior for ButtonFunction {
+    fn apply(&self, grid: &mut Grid, x: i32, y: i32) {
+        match self {
+            ButtonFunction::Nothing => {}
+            // XOR buttons toggle every cell they affect once
+            ButtonFunction::OneArrow(_)
+            | ButtonFunction::TwoArrow(_)
+            | ButtonFunction::FourArrow
+            | ButtonFunction::AroundEight
+            | ButtonFunction::Knight
+            | ButtonFunction::CrossDiagonal => {
+                let cells = self.affected_cells(grid, x, y);
+                grid.toggle_cells(&cells);
+            }
+            ButtonFunction::Rotate(is_clockwise) => {
+                grid.solve_rotate(x, y, *is_clockwise);
+            }
+            ButtonFunction::Shift(is_right) => {
+                grid.solve_shift(y, *is_right);
+            }
+            ButtonFunction::Symmetry(is_horizontal) => {
+                grid.solve_symmetry(x, y, *is_horizontal);
+            }
+            ButtonFunction::ColumnShift(is_up) => {
+                grid.solve_column_shift(x, *is_up);
+            }
+            ButtonFunction::BlockFlip => {
+                grid.solve_blockflip(x, y);
+            }
+            ButtonFunction::Custom(behavior) => behavior.apply(grid, x, y),
+        }
+    }
+
+    fn is_linear(&self) -> bool {
+        ButtonFunction::is_linear(self)
+    }
+
+    fn inverse(&self, grid: &Grid, x: i32, y: i32) -> Option<ButtonFunction> {
+        if let ButtonFunction::Custom(behavior) = self {
+            return behavior.inverse(grid, x, y);
+        }
+
+        // Moves with an opposite button undo with it, the others with themselves or an unclick
+        let inverse = behavior::invert(grid, x, y)?;
+        match self {
+            ButtonFunction::Rotate(is_clockwise) => Some(ButtonFunction::Rotate(!is_clockwise)),
+            ButtonFunction::ColumnShift(is_up) => Some(ButtonFunction::ColumnShift(!is_up)),
+            _ => Some(inverse),
+        }
+    }
+
+    fn affected_cells(&self, grid: &Grid, x: i32, y: i32) -> Vec<Coordinate> {
+        let mut result = Vec::new();
+
+        match self {
+            ButtonFunction::Nothing => {}
+            ButtonFunction::OneArrow(dir) => {
+                let (dx, dy) = dir.delta();
+                result.push(Coordinate::new(x, y));
+                grid.collect_ray(&mut result, x, y, dx, dy);
+            }
+            ButtonFunction::TwoArrow(dir) => {
+                let (dx, dy) = dir.delta();
+                result.push(Coordinate::new(x, y));
+                grid.collect_ray(&mut result, x, y, dx, dy);
+                grid.collect_ray(&mut result, x, y, -dx, -dy);
+            }
+            ButtonFunction::FourArrow => {
+                result.push(Coordinate::new(x, y));
+                for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                    grid.collect_ray(&mut result, x, y, dx, dy);
+                }
+            }
+            ButtonFunction::Rotate(_) | ButtonFunction::AroundEight => {
+                result.push(Coordinate::new(x, y));
+                for (dx, dy) in AROUND_DIRECTIONS {
+                    grid.collect_neighbor(&mut result, x, y, dx, dy);
+                }
+            }
+            ButtonFunction::Symmetry(is_horizontal) => {
+                result.push(Coordinate::new(x, y));
+                if *is_horizontal {
+                    for dx in [-1, 1] {
+                        if let Some(side) = grid.step(x, y, dx, 0) {
+                            result.extend(grid.column_cells(side.x));
+                        }
+                    }
+                } else {
+                    for dy in [-1, 1] {
+                        if let Some(side) = grid.step(x, y, 0, dy) {
+                            result.extend(grid.row_cells(side.y));
+                        }
+                    }
+                }
+            }
+            ButtonFunction::Shift(_) => return grid.row_cells(y),
+            ButtonFunction::Knight => {
+                result.push(Coordinate::new(x, y));
+                for (dx, dy) in KNIGHT_DIRECTIONS {
+                    grid.collect_neighbor(&mut result, x, y, dx, dy);
+                }
+            }
+            ButtonFunction::ColumnShift(_) => return grid.column_cells(x),
+            ButtonFunction::CrossDiagonal => {
+                result.push(Coordinate::new(x, y));
+                for (dx, dy) in DIAGONAL_DIRECTIONS {
+                    grid.collect_ray(&mut result, x, y, dx, dy);
+                }
+            }
+            ButtonFunction::BlockFlip => {
+                result.push(Coordinate::new(x, y));
+                for &(dx, dy) in AROUND_DIRECTIONS[..4].iter() {
+                    let pair = (grid.neighbor(x, y, dx, dy), grid.neighbor(x, y, -dx, -dy));
+                    if let (Some(a), Some(b)) = pair {
+                        result.push(a);
+                        result.push(b);
+                    }
+                }
+            }
+            ButtonFunction::Custom(behavior) => return behavior.affected_cells(grid, x, y),
+        }
+
+        result
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -28,6 +185,21 @@ pub enum OneArrow {
     RightDown,
 }
 
+impl OneArrow {
+    pub fn delta(&self) -> (i32, i32) {
+        match self {
+            OneArrow::Up => (0, 1),
+            OneArrow::Down => (0, -1),
+            OneArrow::Left => (-1, 0),
+            OneArrow::Right => (1, 0),
+            OneArrow::LeftUp => (-1, 1),
+            OneArrow::RightUp => (1, 1),
+            OneArrow::LeftDown => (-1, -1),
+            OneArrow::RightDown => (1, -1),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum TwoArrow {
     BothHorizontal,
@@ -36,6 +208,44 @@ pub enum TwoArrow {
     LeftDownRightUp,
 }
 
+impl TwoArrow {
+    /// Direction of one of the two arrows, the other one points the opposite way
+    pub fn delta(&self) -> (i32, i32) {
+        match self {
+            TwoArrow::BothHorizontal => (1, 0),
+            TwoArrow::BothVertical => (0, 1),
+            TwoArrow::LeftUpRightDown => (-1, 1),
+            TwoArrow::LeftDownRightUp => (-1, -1),
+        }
+    }
+}
+
+// Neighbours in clockwise order, starting from the one above
+const AROUND_DIRECTIONS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+// Cells a knight's move away, clockwise from the one up and to the right
+const KNIGHT_DIRECTIONS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+const DIAGONAL_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
 //
 // Coordinate
 //
@@ -58,6 +268,8 @@ pub struct Button {
     pub coord: Coordinate,
     pub is_on: bool,
     pub func: ButtonFunction,
+    /// Hole in the board, it holds no light and has no button
+    pub is_void: bool,
 }
 
 impl Button {
@@ -66,6 +278,7 @@ impl Button {
             coord: Coordinate::new(x, y),
             is_on: false,
             func: ButtonFunction::Nothing,
+            is_void: false,
         }
     }
 
@@ -74,68 +287,64 @@ impl Button {
     }
 }
 
+//
+// Topology
+//
+/// How the edges of a board connect. Wrapping only applies along sides of at
+/// least 3 cells, so a neighbour is never the button itself or its opposite one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    /// Cells and rays end at the edges
+    #[default]
+    Bounded,
+    /// Left connects to right and top to bottom
+    Torus,
+    /// Left connects to right, top and bottom are edges
+    Cylinder,
+}
+
+impl Topology {
+    pub fn wraps_x(&self) -> bool {
+        matches!(self, Topology::Torus | Topology::Cylinder)
+    }
+
+    pub fn wraps_y(&self) -> bool {
+        matches!(self, Topology::Torus)
+    }
+}
+
+/// What arrow rays do when they reach a void cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RayMode {
+    /// The ray ends before the void
+    #[default]
+    Block,
+    /// The ray jumps over the void and goes on
+    Skip,
+}
+
 //
 // Grid
 //
+/// Board of buttons that can be clicked, indexed with (0, 0) at the bottom left
 #[derive(Debug)]
 pub struct Grid {
     buttons: Vec<Button>,
     width: i32,
     height: i32,
-}
-
-fn convert_subtype_to_func(subtype: u8) -> ButtonFunction {
-    match subtype {
-        // One Arrow Linear
-        1 => ButtonFunction::OneArrow(OneArrow::Up),
-        2 => ButtonFunction::OneArrow(OneArrow::Down),
-        3 => ButtonFunction::OneArrow(OneArrow::Left),
-        4 => ButtonFunction::OneArrow(OneArrow::Right),
-
-        // One Arrow Diagonal
-        5 => ButtonFunction::OneArrow(OneArrow::LeftUp),
-        6 => ButtonFunction::OneArrow(OneArrow::RightUp),
-        7 => ButtonFunction::OneArrow(OneArrow::LeftDown),
-        8 => ButtonFunction::OneArrow(OneArrow::RightDown),
-
-        // Two Arrow Linear
-        9 => ButtonFunction::TwoArrow(TwoArrow::BothHorizontal),
-        10 => ButtonFunction::TwoArrow(TwoArrow::BothVertical),
-
-        // Four Arrow
-        11 => ButtonFunction::FourArrow,
-
-        // Two Arrow Diagonal
-        12 => ButtonFunction::TwoArrow(TwoArrow::LeftUpRightDown),
-        13 => ButtonFunction::TwoArrow(TwoArrow::LeftDownRightUp),
-
-        // Rotate
-        14 => ButtonFunction::Rotate(true),  // Clockwise
-        15 => ButtonFunction::Rotate(false), // Counter-Clockwise
-
-        // Symmetry
-        16 => ButtonFunction::Symmetry(true),  // Horizontal
-        17 => ButtonFunction::Symmetry(false), // Vertical
-
-        // Shift
-        18 => ButtonFunction::Shift(false), // Left
-        19 => ButtonFunction::Shift(true),  // Right
-
-        // Around Eight
-        20 => ButtonFunction::AroundEight,
-
-        // Unknown
-        _ => ButtonFunction::Nothing,
-    }
+    topology: Topology,
+    ray_mode: RayMode,
 }
 
 impl Grid {
     pub fn new(width: i32, height: i32) -> Grid {
-        let total_buttons = width * height;
+        let total_buttons = width as usize * height as usize;
         let mut result = Grid {
-            buttons: Vec::with_capacity(total_buttons as usize),
+            buttons: Vec::with_capacity(total_buttons),
             width,
             height,
+            topology: Topology::Bounded,
+            ray_mode: RayMode::Block,
         };
 
         for y in 0..height {
@@ -147,21 +356,34 @@ impl Grid {
         result
     }
 
+    /// Grid of a level, with subtypes read through the global registry
     pub fn from_level(level: &ToggleLevel) -> Grid {
+        Grid::from_level_with(level, &behavior::global())
+    }
+
+    pub fn from_level_with(level: &ToggleLevel, registry: &Registry) -> Grid {
         let mut result = Grid::new(
             level.width.try_into().unwrap(),
             level.height.try_into().unwrap(),
         );
+        result.topology = level.topology;
+        result.ray_mode = level.ray_mode;
 
-        for y in 0..level.height {
-            for x in 0..level.width {
-                let state_index = usize::try_from(x * level.height + y).unwrap();
-                let subtype_index = usize::try_from(y * level.width + x).unwrap();
+        let width = level.width as usize;
+        let height = level.height as usize;
+        for y in 0..height {
+            for x in 0..width {
+                let state_index = x * height + y;
+                let subtype_index = y * width + x;
 
                 let btn = result.at_mut(x as i32, y as i32).unwrap();
 
-                btn.is_on = level.states.get(state_index);
-                btn.func = convert_subtype_to_func(level.subtypes[subtype_index]);
+                if level.is_void(state_index) {
+                    btn.is_void = true;
+                } else {
+                    btn.is_on = level.states.get(state_index);
+                    btn.func = registry.function(level.subtypes[subtype_index]);
+                }
             }
         }
         result
@@ -175,6 +397,22 @@ impl Grid {
         self.height
     }
 
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    pub fn set_topology(&mut self, topology: Topology) {
+        self.topology = topology;
+    }
+
+    pub fn ray_mode(&self) -> RayMode {
+        self.ray_mode
+    }
+
+    pub fn set_ray_mode(&mut self, ray_mode: RayMode) {
+        self.ray_mode = ray_mode;
+    }
+
     pub fn at(&self, x: i32, y: i32) -> Option<&Button> {
         let index = y * self.width + x;
         self.buttons.get(index as usize)
@@ -185,6 +423,10 @@ impl Grid {
         self.buttons.get_mut(index as usize)
     }
 
+    pub fn is_void(&self, x: i32, y: i32) -> bool {
+        self.at(x, y).unwrap().is_void
+    }
+
     pub fn click(&mut self, x: i32, y: i32) {
         self.click_button(x, y)
     }
@@ -193,6 +435,32 @@ impl Grid {
         (x >= 0 && x < self.width) && (y >= 0 && y < self.height)
     }
 
+    /// Cell at (x + dx, y + dy), wrapped around the edges the topology connects.
+    /// None if it is off the board or void.
+    pub fn neighbor(&self, x: i32, y: i32, dx: i32, dy: i32) -> Option<Coordinate> {
+        self.step(x, y, dx, dy)
+            .filter(|cell| !self.is_void(cell.x, cell.y))
+    }
+
+    // Like neighbor, but void cells are returned too
+    fn step(&self, x: i32, y: i32, dx: i32, dy: i32) -> Option<Coordinate> {
+        let mut x2 = x + dx;
+        let mut y2 = y + dy;
+
+        if self.topology.wraps_x() && self.width >= 3 {
+            x2 = x2.rem_euclid(self.width);
+        }
+        if self.topology.wraps_y() && self.height >= 3 {
+            y2 = y2.rem_euclid(self.height);
+        }
+
+        if self.check_range(x2, y2) {
+            Some(Coordinate::new(x2, y2))
+        } else {
+            None
+        }
+    }
+
     pub fn print(&self) {
         for y in 0..self.height {
             for x in 0..self.width {
@@ -206,7 +474,7 @@ impl Grid {
     }
 
     pub fn get_states(&self) -> BitArray {
-        let mut result = BitArray::new((self.width * self.height).try_into().unwrap());
+        let mut result = BitArray::new(self.buttons.len());
 
         let mut i = 0;
         for x in 0..self.width {
@@ -237,153 +505,94 @@ impl Grid {
         }
     }
 
+    /// Cells whose state may change when the button at (x, y) is clicked
+    pub fn affected_cells(&self, x: i32, y: i32) -> Vec<Coordinate> {
+        self.at(x, y).unwrap().func.affected_cells(self, x, y)
+    }
+
     // Clicks an button
     fn click_button(&mut self, x: i32, y: i32) {
-        let btn = self.at_mut(x, y).unwrap();
-
-        match btn.func {
-            ButtonFunction::Nothing => {}
-            ButtonFunction::OneArrow(dir) => {
-                self.solve_onearrow(x, y, dir);
-            }
-            ButtonFunction::TwoArrow(dir) => {
-                self.solve_twoarrow(x, y, dir);
-            }
-            ButtonFunction::FourArrow => {
-                self.solve_fourarrow(x, y);
-            }
-            ButtonFunction::Rotate(is_clockwise) => {
-                self.solve_rotate(x, y, is_clockwise);
-            }
-            ButtonFunction::Shift(is_right) => {
-                self.solve_shift(y, is_right);
-            }
-            ButtonFunction::Symmetry(is_horizontal) => {
-                self.solve_symmetry(x, y, is_horizontal);
-            }
-            ButtonFunction::AroundEight => {
-                self.solve_aroundeight(x, y);
+        let func = self.at(x, y).unwrap().func.clone();
+        func.apply(self, x, y);
+
+        // Custom behaviors may write to voids, which never hold a light.
+        // The built-in functions leave voids alone.
+        if let ButtonFunction::Custom(behavior) = &func {
+            for cell in behavior.affected_cells(self, x, y) {
+                let btn = self.at_mut(cell.x, cell.y).unwrap();
+                if btn.is_void {
+                    btn.is_on = false;
+                }
             }
         }
     }
 
-    fn solve_onearrow(&mut self, x: i32, y: i32, dir: OneArrow) {
-        let mut dx = 0;
-        let mut dy = 0;
-
-        match dir {
-            OneArrow::Up => dy = 1,
-            OneArrow::Down => dy = -1,
-            OneArrow::Left => dx = -1,
-            OneArrow::Right => dx = 1,
-            OneArrow::LeftUp => {
-                dx = -1;
-                dy = 1
-            }
-            OneArrow::RightUp => {
-                dx = 1;
-                dy = 1
-            }
-            OneArrow::LeftDown => {
-                dx = -1;
-                dy = -1
-            }
-            OneArrow::RightDown => {
-                dx = 1;
-                dy = -1
-            }
-        }
-
-        self.toggle_follow_direction(x, y, dx, dy);
+    /// Cells of row y that are not void, from left to right
+    pub fn row_cells(&self, y: i32) -> Vec<Coordinate> {
+        (0..self.width)
+            .filter(|&x| !self.is_void(x, y))
+            .map(|x| Coordinate::new(x, y))
+            .collect()
     }
 
-    fn solve_twoarrow(&mut self, x: i32, y: i32, dir: TwoArrow) {
-        let mut dx: i32 = 0;
-        let mut dy: i32 = 0;
-
-        match dir {
-            TwoArrow::BothHorizontal => {
-                dx = 1;
-            },
-            TwoArrow::BothVertical => {
-                dy = 1;
-            },
-            TwoArrow::LeftUpRightDown => {
-                dx = -1;
-                dy = 1;
-            }
-            TwoArrow::LeftDownRightUp => {
-                dx = -1;
-                dy = -1;
-            }
-        }
-
-        let btn = self.at_mut(x, y).unwrap();
-        btn.toggle();
-
-        self.toggle_follow_direction(x + dx, y + dy, dx, dy);
-        self.toggle_follow_direction(x - dx, y - dy, -dx, -dy);
+    /// Cells of column x that are not void, from bottom to top
+    pub fn column_cells(&self, x: i32) -> Vec<Coordinate> {
+        (0..self.height)
+            .filter(|&y| !self.is_void(x, y))
+            .map(|y| Coordinate::new(x, y))
+            .collect()
     }
 
-    fn toggle_follow_direction(&mut self, start_x: i32, start_y: i32, dx: i32, dy: i32) {
-        let mut x2 = start_x;
-        let mut y2 = start_y;
-
-        while self.check_range(x2, y2) {
-            let btn = self.at_mut(x2, y2).unwrap();
+    fn toggle_cells(&mut self, cells: &[Coordinate]) {
+        for cell in cells.iter() {
+            let btn = self.at_mut(cell.x, cell.y).unwrap();
             btn.toggle();
-
-            x2 += dx;
-            y2 += dy;
         }
     }
 
-    fn solve_fourarrow(&mut self, x: i32, y: i32) {
-        let btn = self.at_mut(x, y).unwrap();
-        btn.toggle();
+    // Adds the cells from the one next to (x, y) onwards in direction (dx, dy),
+    // until the ray leaves the board, is blocked by a void or wraps around onto
+    // the button or a cell already added
+    fn collect_ray(&self, result: &mut Vec<Coordinate>, x: i32, y: i32, dx: i32, dy: i32) {
+        let start = Coordinate::new(x, y);
+        let mut current = start;
 
-        for x2 in 0..self.width() {
-            if x == x2 {
-                continue;
+        while let Some(next) = self.step(current.x, current.y, dx, dy) {
+            if next == start || result.contains(&next) {
+                break;
             }
+            current = next;
 
-            let btn = self.at_mut(x2, y).unwrap();
-            btn.toggle();
+            if self.is_void(next.x, next.y) {
+                match self.ray_mode {
+                    RayMode::Block => break,
+                    RayMode::Skip => continue,
+                }
+            }
+            result.push(next);
         }
+    }
 
-        for y2 in 0..self.height() {
-            if y == y2 {
-                continue;
+    fn collect_neighbor(&self, result: &mut Vec<Coordinate>, x: i32, y: i32, dx: i32, dy: i32) {
+        if let Some(cell) = self.neighbor(x, y, dx, dy) {
+            if !result.contains(&cell) {
+                result.push(cell);
             }
-            let btn = self.at_mut(x, y2).unwrap();
-            btn.toggle();
         }
     }
 
     fn solve_rotate(&mut self, x: i32, y: i32, is_clockwise: bool) {
-        let directions = [
-            (0, 1),
-            (1, 1),
-            (1, 0),
-            (1, -1),
-            (0, -1),
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-        ];
-
         let btn = self.at_mut(x, y).unwrap();
         btn.toggle();
 
+        let ring = AROUND_DIRECTIONS.map(|(dx, dy)| self.neighbor(x, y, dx, dy));
+
         let mut old_states: [bool; 8] = [false; 8];
         let mut new_states: [bool; 8] = [false; 8];
 
         for i in 0..8 {
-            let x2 = x + directions[i].0;
-            let y2 = y + directions[i].1;
-
-            if self.check_range(x2, y2) {
-                let btn = self.at(x2, y2).unwrap();
+            if let Some(cell) = ring[i] {
+                let btn = self.at(cell.x, cell.y).unwrap();
                 old_states[i] = btn.is_on;
             }
         }
@@ -391,127 +600,130 @@ impl Grid {
         if is_clockwise {
             new_states[0] = old_states[7];
 
-            for i in 1..8 {
-                new_states[i] = old_states[i - 1];
-            }
+            new_states[1..8].copy_from_slice(&old_states[0..7]);
         } else {
             new_states[7] = old_states[0];
 
-            for i in 0..7 {
-                new_states[i] = old_states[i + 1];
-            }
+            new_states[0..7].copy_from_slice(&old_states[1..8]);
         }
 
         for i in 0..8 {
-            let x2 = x + directions[i].0;
-            let y2 = y + directions[i].1;
-
-            if self.check_range(x2, y2) {
-                let btn = self.at_mut(x2, y2).unwrap();
+            if let Some(cell) = ring[i] {
+                let btn = self.at_mut(cell.x, cell.y).unwrap();
                 btn.is_on = new_states[i];
             }
         }
     }
 
+    // Moves the row by one cell. Shifting right wraps the last light around to
+    // the first cell. Shifting left wraps the first light around to the last
+    // cell, but the light moving into the first cell is lost. Lights move
+    // along the cells that are not void.
     fn solve_shift(&mut self, y: i32, is_right: bool) {
-        let mut old_states: [bool; 16] = [false; 16];
-        let mut new_states: [bool; 16] = [false; 16];
+        let cells = self.row_cells(y);
+        self.rotate_cells(&cells, is_right);
 
-        for i in 0..self.width {
-            let btn = self.at(i, y).unwrap();
-            old_states[i as usize] = btn.is_on;
+        if !is_right && cells.len() > 1 {
+            let first = cells[0];
+            self.at_mut(first.x, first.y).unwrap().is_on = false;
         }
+    }
 
-        if is_right {
-            new_states[0] = old_states[(self.width - 1) as usize];
-            for i in 1..self.width {
-                new_states[i as usize] = old_states[(i - 1) as usize];
-            }
+    // Moves the light of every cell to the next one, the last one to the first
+    fn rotate_cells(&mut self, cells: &[Coordinate], is_forward: bool) {
+        let mut states: Vec<bool> = cells
+            .iter()
+            .map(|cell| self.at(cell.x, cell.y).unwrap().is_on)
+            .collect();
+
+        if is_forward {
+            states.rotate_right(1);
         } else {
-            new_states[(self.width - 1) as usize] = old_states[0];
-            for i in 1..(self.width - 1) {
-                new_states[i as usize] = old_states[(i + 1) as usize];
-            }
+            states.rotate_left(1);
         }
 
-        for i in 0..self.width {
-            let btn = self.at_mut(i, y).unwrap();
-            btn.is_on = new_states[i as usize];
+        for (cell, is_on) in cells.iter().zip(states) {
+            let btn = self.at_mut(cell.x, cell.y).unwrap();
+            btn.is_on = is_on;
         }
     }
 
+    // Swaps the columns (or rows) on both sides of the button. A light swapped
+    // into a void or off the board is lost.
     fn solve_symmetry(&mut self, x: i32, y: i32, is_horizontal: bool) {
         let btn = self.at_mut(x, y).unwrap();
         btn.toggle();
 
         if is_horizontal {
-            let left_x = x - 1;
-            let right_x = x + 1;
+            let left = self.step(x, y, -1, 0).map(|c| c.x);
+            let right = self.step(x, y, 1, 0).map(|c| c.x);
 
-            let exists_left = self.check_range(left_x, y);
-            let exists_right = self.check_range(right_x, y);
             for y2 in 0..self.height {
-
-                if exists_left && exists_right {
-                    let left_state = self.at(left_x, y2).unwrap().is_on;
-                    let right_state = self.at(right_x, y2).unwrap().is_on;
-
-                    self.at_mut(right_x, y2).unwrap().is_on = left_state;
-                    self.at_mut(left_x, y2).unwrap().is_on = right_state;
-
-                } else if exists_left {
-                    self.at_mut(left_x, y2).unwrap().is_on = false;
-
-                } else if exists_right {
-                    self.at_mut(right_x, y2).unwrap().is_on = false;
+                match (left, right) {
+                    (Some(left_x), Some(right_x)) => {
+                        let left_state = self.at(left_x, y2).unwrap().is_on;
+                        let right_state = self.at(right_x, y2).unwrap().is_on;
+
+                        self.set_light(right_x, y2, left_state);
+                        self.set_light(left_x, y2, right_state);
+                    }
+                    (Some(side_x), None) | (None, Some(side_x)) => {
+                        self.set_light(side_x, y2, false);
+                    }
+                    (None, None) => {}
                 }
             }
         } else {
-            let up_y = y + 1;
-            let down_y = y - 1;
-
-            let exists_up = self.check_range(x, up_y);
-            let exists_down = self.check_range(x, down_y);
+            let up = self.step(x, y, 0, 1).map(|c| c.y);
+            let down = self.step(x, y, 0, -1).map(|c| c.y);
 
             for x2 in 0..self.width {
-                if exists_up && exists_down {
-                    let up_state = self.at(x2, up_y).unwrap().is_on;
-                    let down_state = self.at(x2, down_y).unwrap().is_on;
-
-                    self.at_mut(x2, up_y).unwrap().is_on = down_state;
-                    self.at_mut(x2, down_y).unwrap().is_on = up_state;
-                } else if exists_up {
-                    self.at_mut(x2, up_y).unwrap().is_on = false;
-
-                } else if exists_down {
-                    self.at_mut(x2, down_y).unwrap().is_on = false;
+                match (up, down) {
+                    (Some(up_y), Some(down_y)) => {
+                        let up_state = self.at(x2, up_y).unwrap().is_on;
+                        let down_state = self.at(x2, down_y).unwrap().is_on;
+
+                        self.set_light(x2, up_y, down_state);
+                        self.set_light(x2, down_y, up_state);
+                    }
+                    (Some(side_y), None) | (None, Some(side_y)) => {
+                        self.set_light(x2, side_y, false);
+                    }
+                    (None, None) => {}
                 }
             }
         }
     }
 
-    fn solve_aroundeight(&mut self, x: i32, y: i32) {
-        let directions = [
-            (0, 1),
-            (1, 1),
-            (1, 0),
-            (1, -1),
-            (0, -1),
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-        ];
+    // Voids stay off whatever is written to them
+    fn set_light(&mut self, x: i32, y: i32, is_on: bool) {
+        let btn = self.at_mut(x, y).unwrap();
+        if !btn.is_void {
+            btn.is_on = is_on;
+        }
+    }
+
+    // Rotates the whole column by one cell, wrapping around at the ends
+    fn solve_column_shift(&mut self, x: i32, is_up: bool) {
+        let cells = self.column_cells(x);
+        self.rotate_cells(&cells, is_up);
+    }
 
+    // Turns the 3x3 block around the button by half a turn. Neighbours whose
+    // opposite cell is off the board or void stay where they are.
+    fn solve_blockflip(&mut self, x: i32, y: i32) {
         let btn = self.at_mut(x, y).unwrap();
         btn.toggle();
 
-        for i in 0..8 {
-            let x2 = x + directions[i].0;
-            let y2 = y + directions[i].1;
+        for &(dx, dy) in AROUND_DIRECTIONS[..4].iter() {
+            let pair = (self.neighbor(x, y, dx, dy), self.neighbor(x, y, -dx, -dy));
+
+            if let (Some(a), Some(b)) = pair {
+                let state_a = self.at(a.x, a.y).unwrap().is_on;
+                let state_b = self.at(b.x, b.y).unwrap().is_on;
 
-            if self.check_range(x2, y2) {
-                let btn = self.at_mut(x2, y2).unwrap();
-                btn.toggle();
+                self.at_mut(a.x, a.y).unwrap().is_on = state_b;
+                self.at_mut(b.x, b.y).unwrap().is_on = state_a;
             }
         }
     }
@@ -520,23 +732,129 @@ impl Grid {
 //
 // Toggle Level
 //
-#[derive(Debug)]
+/// Tagged section the reader does not understand, kept so it can be written back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawSection {
+    pub tag: u8,
+    pub data: Vec<u8>,
+}
+
+/// Pattern the lights have to match to clear a level, one bit per cell in the
+/// same column-major order as the states
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Goal {
+    /// State every cared cell has to end in
+    pub target: BitArray,
+    /// Cells whose final state matters, the others may end either way
+    pub care: BitArray,
+}
+
+impl Goal {
+    /// Every light off, which is the goal of levels that do not store one
+    pub fn new(num_cells: usize) -> Goal {
+        let mut care = BitArray::new(num_cells);
+        for i in 0..num_cells {
+            care.set(i, true);
+        }
+
+        Goal {
+            target: BitArray::new(num_cells),
+            care,
+        }
+    }
+
+    /// Number of cared cells whose state differs from the target
+    pub fn diff(&self, states: &BitArray) -> u32 {
+        let num_cells = states.len().min(self.care.len());
+        (0..num_cells)
+            .filter(|&i| self.care.get(i) && states.get(i) != self.target.get(i))
+            .count() as u32
+    }
+
+    pub fn is_reached(&self, states: &BitArray) -> bool {
+        self.diff(states) == 0
+    }
+
+    /// True if the goal is every light off
+    pub fn is_all_off(&self, num_cells: usize) -> bool {
+        (0..num_cells).all(|i| self.care.get(i) && !self.target.get(i))
+    }
+}
+
+/// Level as stored in a level code. Subtypes are row-major, states column-major.
+#[derive(Debug, Clone)]
 pub struct ToggleLevel {
+    /// Leading byte of the code, tells which format version wrote it
+    pub header: u8,
     pub width: u32,
     pub height: u32,
     pub subtypes: Vec<u8>,
     pub states: BitArray,
     pub min_clicks: u32,
+    pub creator: String,
+    /// None when every light has to be turned off
+    pub goal: Option<Goal>,
+    pub topology: Topology,
+    /// Cells that are holes in the board, None when every cell exists
+    pub voids: Option<BitArray>,
+    pub ray_mode: RayMode,
+    pub unknown_sections: Vec<RawSection>,
 }
 
 impl ToggleLevel {
     pub fn new() -> ToggleLevel {
         ToggleLevel {
+            header: 0,
             width: 0,
             height: 0,
             min_clicks: 0,
             subtypes: Vec::new(),
             states: BitArray::new(0),
+            creator: String::new(),
+            goal: None,
+            topology: Topology::Bounded,
+            voids: None,
+            ray_mode: RayMode::Block,
+            unknown_sections: Vec::new(),
+        }
+    }
+
+    /// Number of buttons, computed without overflowing for large boards
+    pub fn num_cells(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub fn subtype(&self, x: u32, y: u32) -> u8 {
+        self.subtypes[(y * self.width + x) as usize]
+    }
+
+    pub fn set_subtype(&mut self, x: u32, y: u32, subtype: u8) {
+        self.subtypes[(y * self.width + x) as usize] = subtype;
+    }
+
+    /// True if the cell at a column-major index is void
+    pub fn is_void(&self, cell: usize) -> bool {
+        self.voids.as_ref().is_some_and(|voids| voids.get(cell))
+    }
+
+    /// The stored goal, or every light off if there is none.
+    /// Void cells are never cared about.
+    pub fn goal(&self) -> Goal {
+        let mut result = match &self.goal {
+            Some(goal) => goal.clone(),
+            None => Goal::new(self.num_cells()),
+        };
+
+        for cell in (0..self.num_cells()).filter(|&cell| self.is_void(cell)) {
+            result.target.set(cell, false);
+            result.care.set(cell, false);
         }
+        result
+    }
+}
+
+impl Default for ToggleLevel {
+    fn default() -> ToggleLevel {
+        ToggleLevel::new()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,212 @@
+use super::astar::{self, SearchLimits, SolverConfig, SolverStats};
+use super::core::{ButtonFunction, Coordinate, Grid, ToggleLevel};
+use super::linear;
+use super::uniqueness;
+use super::verify;
+
+#[derive(Debug, Clone)]
+pub struct DifficultyReport {
+    /// Rating from how often each cell flips along the solution
+    pub flip_score: f64,
+    /// A minimum-click solution
+    pub solution: Vec<Coordinate>,
+    /// Clicks stored in the level, 0 if the creator did not set it
+    pub min_clicks: u32,
+    /// Buttons that can be clicked at every step
+    pub branching_factor: f64,
+    /// Branching factor of a uniform tree as large as the one the search
+    /// explored, None for linear levels which are solved without searching
+    pub effective_branching_factor: Option<f64>,
+    /// Number of minimum-click solutions, None if counting ran out of budget.
+    /// Orders that only swap commuting clicks count as one solution.
+    pub optimal_solutions: Option<u64>,
+    /// Share of the buttons that move lights instead of only toggling them
+    pub non_linear_ratio: f64,
+    /// Search effort, None for linear levels
+    pub stats: Option<SolverStats>,
+}
+
+impl DifficultyReport {
+    pub fn optimal_clicks(&self) -> usize {
+        self.solution.len()
+    }
+
+    /// Clicks the stored `min_clicks` is above the optimum, None if it is not set
+    pub fn min_clicks_gap(&self) -> Option<i64> {
+        if self.min_clicks == 0 {
+            return None;
+        }
+        Some(self.min_clicks as i64 - self.solution.len() as i64)
+    }
+}
+
+/// Rates the level, searching at most astar::DEFAULT_MAX_NODES nodes.
+/// Returns None if it cannot be solved or no minimum-click solution was found.
+pub fn rate(level: &ToggleLevel) -> Option<DifficultyReport> {
+    let mut limits = SearchLimits::new();
+    limits.max_nodes = Some(astar::DEFAULT_MAX_NODES);
+    rate_with(level, &limits)
+}
+
+/// Rates the level, giving up when the search for a minimum-click solution hits `limits`
+pub fn rate_with(level: &ToggleLevel, limits: &SearchLimits) -> Option<DifficultyReport> {
+    let (solution, stats) = match linear::solve_linear(level) {
+        linear::Result::Success(orders) => (orders, None),
+        // The minimum of a linear level too large to solve exactly is out of reach
+        linear::Result::Fail | linear::Result::Approximate(_) => return None,
+        linear::Result::NotLinear => {
+            let mut config = SolverConfig::optimal(level);
+            config.limits = *limits;
+            let output = astar::run_astar_with(level, &config);
+            match output.result {
+                astar::Result::Success(orders) => (orders, Some(output.stats)),
+                _ => return None,
+            }
+        }
+    };
+
+    let grid = Grid::from_level(level);
+    let mut clickable = 0;
+    let mut non_linear = 0;
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            let func = &grid.at(x, y).unwrap().func;
+            if let ButtonFunction::Nothing = func {
+                continue;
+            }
+
+            clickable += 1;
+            if !func.is_linear() {
+                non_linear += 1;
+            }
+        }
+    }
+
+    // Every click of a linear level commutes, so its solutions are sets of buttons
+    let optimal_solutions = match linear::count_minimum_solutions(level) {
+        Some(count) => Some(count),
+        // Too many redundant buttons to count, a search would not finish either
+        None if non_linear == 0 => None,
+        None => {
+            let count = uniqueness::count_with_clicks(level, solution.len(), u64::MAX);
+            count.complete.then_some(count.count)
+        }
+    };
+
+    Some(DifficultyReport {
+        flip_score: flip_score(level, &solution),
+        min_clicks: level.min_clicks,
+        branching_factor: clickable as f64,
+        effective_branching_factor: stats
+            .and_then(|stats| effective_branching_factor(stats.nodes_generated, solution.len())),
+        optimal_solutions,
+        non_linear_ratio: match clickable {
+            0 => 0.0,
+            _ => non_linear as f64 / clickable as f64,
+        },
+        stats,
+        solution,
+    })
+}
+
+/// Geometric mean over all cells that are not void of how many times each one
+/// flips while `orders` is replayed, scaled so that 0 means no cell flips twice
+pub fn flip_score(level: &ToggleLevel, orders: &[Coordinate]) -> f64 {
+    let mut diff: Vec<u32> = vec![0; level.num_cells()];
+
+    let result = verify::replay(level, orders);
+    for step in result.states.windows(2) {
+        for (i, count) in diff.iter_mut().enumerate() {
+            if step[0].get(i) != step[1].get(i) {
+                *count += 1;
+            }
+        }
+    }
+
+    let mut mul: f64 = 1.0;
+    for &count in diff.iter().filter(|&&count| count != 0) {
+        mul *= count as f64;
+    }
+    let total_buttons = (0..level.num_cells())
+        .filter(|&cell| !level.is_void(cell))
+        .count();
+    mul = f64::powf(mul, 1.0 / total_buttons as f64);
+
+    (mul - 1.0) * 10.0
+}
+
+// Solves nodes = b + b^2 + ... + b^depth for b by bisection
+fn effective_branching_factor(nodes: usize, depth: usize) -> Option<f64> {
+    if depth == 0 || nodes == 0 {
+        return None;
+    }
+
+    let tree_size = |b: f64| (1..=depth).map(|d| b.powi(d as i32)).sum::<f64>();
+
+    let mut low = 0.0;
+    let mut high = nodes as f64;
+    for _ in 0..100 {
+        let middle = (low + high) / 2.0;
+        if tree_size(middle) < nodes as f64 {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    Some((low + high) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::testing::{random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::{BitArray, Rng};
+
+    #[test]
+    fn rated_clicks_match_breadth_first_search() {
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut rng = Rng::new(41);
+        for (width, height) in [(2, 2), (3, 2), (3, 3)] {
+            for i in 0..30 {
+                let mut level = random_level(width, height, &subtypes, &mut rng);
+                if i % 2 == 1 {
+                    random_goal(&mut level, &mut rng);
+                }
+
+                let clicks = shortest_solution(&level);
+                match rate(&level) {
+                    Some(report) => {
+                        assert_eq!(Some(report.optimal_clicks()), clicks);
+                        assert!(verify::is_solution(&level, &report.solution));
+                        assert!(report.optimal_solutions.unwrap_or(1) >= 1);
+                    }
+                    None => assert_eq!(clicks, None),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn search_limits_stop_rating() {
+        let mut rng = Rng::new(43);
+        let level = random_level(5, 5, &[14, 15, 16, 17], &mut rng);
+
+        let mut limits = SearchLimits::new();
+        limits.max_nodes = Some(10);
+        assert!(rate_with(&level, &limits).is_none());
+    }
+
+    #[test]
+    fn cells_that_flip_once_score_zero() {
+        let mut level = ToggleLevel::new();
+        level.width = 1;
+        level.height = 1;
+        level.subtypes = vec![11];
+        level.states = BitArray::new(1);
+
+        let click = Coordinate::new(0, 0);
+        assert_eq!(flip_score(&level, &[click]), 0.0);
+        assert_eq!(flip_score(&level, &[click, click]), 10.0);
+    }
+}
@@ -0,0 +1,416 @@
+use std::ops::RangeInclusive;
+
+use super::astar::{self, SearchLimits, SolverConfig};
+use super::bitgrid::BitGrid;
+use super::core::{ButtonFunction, Coordinate, Grid, RayMode, ToggleLevel, Topology};
+use super::difficulty;
+use super::heuristic::{Admissible, Heuristic};
+use super::linear;
+use super::utils::{BitArray, Rng};
+use super::writer;
+
+// Buttons tried before a backward click is given up for one step
+const UNCLICK_ATTEMPTS: usize = 16;
+// Candidates without improvement before the targeted search starts over
+const RESTART_AFTER: usize = 200;
+
+pub struct GeneratorConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Subtypes the buttons are drawn from, repeat one to make it more likely
+    pub subtypes: Vec<u8>,
+    /// Backward clicks applied from the all-off board
+    pub scramble_clicks: usize,
+    pub topology: Topology,
+    /// Holes of the board in column-major order, None for a full rectangle
+    pub voids: Option<BitArray>,
+    pub ray_mode: RayMode,
+    pub seed: u64,
+    /// Limits for the optimal search of levels that are not linear
+    pub limits: SearchLimits,
+}
+
+impl GeneratorConfig {
+    /// Every button type with equal weight
+    pub fn new(width: u32, height: u32, seed: u64) -> GeneratorConfig {
+        GeneratorConfig {
+            width,
+            height,
+            subtypes: (1..=25).collect(),
+            scramble_clicks: (width as usize * height as usize / 2).max(1),
+            topology: Topology::Bounded,
+            voids: None,
+            ray_mode: RayMode::Block,
+            seed,
+            limits: SearchLimits::new(),
+        }
+    }
+
+    fn num_cells(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+pub struct Generated {
+    /// Level with `min_clicks` set to the optimal number of clicks
+    pub level: ToggleLevel,
+    pub code: String,
+    /// Backward clicks that built the level, reversed they solve it
+    pub scramble: Vec<Coordinate>,
+    /// A minimum-click solution
+    pub solution: Vec<Coordinate>,
+}
+
+/// Builds a random level that is solvable by construction. Returns None if no
+/// subtype has a function, the optimal search hits its limits or the board is
+/// too large for a level code.
+pub fn generate(config: &GeneratorConfig) -> Option<Generated> {
+    let mut rng = Rng::new(config.seed);
+
+    let mut level = ToggleLevel::new();
+    level.width = config.width;
+    level.height = config.height;
+    level.topology = config.topology;
+    level.voids = config.voids.clone();
+    level.ray_mode = config.ray_mode;
+    level.subtypes = (0..config.num_cells())
+        .map(|_| *rng.choose(&config.subtypes))
+        .collect();
+    level.states = BitArray::new(config.num_cells());
+
+    let scramble = scramble(&mut level, config.scramble_clicks, &mut rng)?;
+    let solution = solve_optimal(&level, &config.limits)?;
+    level.min_clicks = solution.len() as u32;
+
+    Some(Generated {
+        code: writer::encode(&level).ok()?,
+        level,
+        scramble,
+        solution,
+    })
+}
+
+/// Sets the states of `level` by clicking backwards from the target of its goal,
+/// returning the backward clicks. None if the level has no clickable button.
+pub fn scramble(level: &mut ToggleLevel, clicks: usize, rng: &mut Rng) -> Option<Vec<Coordinate>> {
+    let grid = Grid::from_level(level);
+
+    let mut buttons = Vec::new();
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            if !matches!(grid.at(x, y).unwrap().func, ButtonFunction::Nothing) {
+                buttons.push(Coordinate::new(x, y));
+            }
+        }
+    }
+    if buttons.is_empty() {
+        return None;
+    }
+
+    let mut bit_grid = BitGrid::from_grid(&grid);
+    bit_grid.set_states(&level.goal().target);
+
+    let mut result = Vec::with_capacity(clicks);
+    for _ in 0..clicks {
+        for _ in 0..UNCLICK_ATTEMPTS {
+            let coord = *rng.choose(&buttons);
+            if bit_grid.unclick(coord.x, coord.y) {
+                result.push(coord);
+                break;
+            }
+        }
+    }
+
+    level.states = bit_grid.get_states();
+    Some(result)
+}
+
+fn solve_optimal(level: &ToggleLevel, limits: &SearchLimits) -> Option<Vec<Coordinate>> {
+    match linear::solve_linear(level) {
+        linear::Result::Success(orders) => Some(orders),
+        linear::Result::Fail => None,
+        linear::Result::Approximate(_) | linear::Result::NotLinear => {
+            let mut config = SolverConfig::optimal(level);
+            config.limits = *limits;
+
+            match astar::run_astar_with(level, &config).result {
+                astar::Result::Success(orders) => Some(orders),
+                _ => None,
+            }
+        }
+    }
+}
+
+//
+// Targeted Generation
+//
+// Hill climbing over the subtypes and the backward clicks. Every candidate
+// is still built by clicking backwards, so it stays solvable.
+//
+pub struct Target {
+    pub min_clicks: RangeInclusive<u32>,
+    /// Range of difficulty::flip_score
+    pub difficulty: RangeInclusive<f64>,
+    /// Candidates built before giving up
+    pub max_candidates: usize,
+}
+
+impl Target {
+    pub fn new(min_clicks: RangeInclusive<u32>, difficulty: RangeInclusive<f64>) -> Target {
+        Target {
+            min_clicks,
+            difficulty,
+            max_candidates: 10000,
+        }
+    }
+
+    // Difficulty that counts as on target while it is still unknown
+    fn difficulty_hint(&self) -> f64 {
+        *self.difficulty.start()
+    }
+
+    // How far the level is outside the ranges, 0 when it meets them
+    fn distance(&self, min_clicks: u32, difficulty: f64) -> f64 {
+        let clicks = min_clicks as f64;
+        let clicks_range = *self.min_clicks.start() as f64..=*self.min_clicks.end() as f64;
+
+        distance_to(clicks, &clicks_range) + distance_to(difficulty, &self.difficulty)
+    }
+}
+
+fn distance_to(value: f64, range: &RangeInclusive<f64>) -> f64 {
+    if value < *range.start() {
+        range.start() - value
+    } else if value > *range.end() {
+        value - range.end()
+    } else {
+        0.0
+    }
+}
+
+pub struct TargetedOutput {
+    pub result: Option<Generated>,
+    /// Levels built and solved during the search
+    pub candidates: usize,
+}
+
+#[derive(Clone)]
+struct Candidate {
+    subtypes: Vec<u8>,
+    scramble: Vec<Coordinate>,
+}
+
+impl Candidate {
+    fn random(config: &GeneratorConfig, rng: &mut Rng) -> Candidate {
+        let subtypes = (0..config.num_cells())
+            .map(|_| *rng.choose(&config.subtypes))
+            .collect();
+        let scramble = (0..config.scramble_clicks)
+            .map(|_| Candidate::random_coord(config, rng))
+            .collect();
+
+        Candidate { subtypes, scramble }
+    }
+
+    fn random_coord(config: &GeneratorConfig, rng: &mut Rng) -> Coordinate {
+        Coordinate::new(
+            rng.below(config.width as usize) as i32,
+            rng.below(config.height as usize) as i32,
+        )
+    }
+
+    fn mutate(&self, config: &GeneratorConfig, rng: &mut Rng) -> Candidate {
+        let mut result = self.clone();
+
+        match rng.below(4) {
+            0 => {
+                let index = rng.below(result.subtypes.len());
+                result.subtypes[index] = *rng.choose(&config.subtypes);
+            }
+            1 => {
+                let index = rng.below(result.scramble.len() + 1);
+                result
+                    .scramble
+                    .insert(index, Candidate::random_coord(config, rng));
+            }
+            2 if !result.scramble.is_empty() => {
+                let index = rng.below(result.scramble.len());
+                result.scramble.remove(index);
+            }
+            _ if !result.scramble.is_empty() => {
+                let index = rng.below(result.scramble.len());
+                result.scramble[index] = Candidate::random_coord(config, rng);
+            }
+            _ => result.scramble.push(Candidate::random_coord(config, rng)),
+        }
+
+        result
+    }
+
+    // Clicks the scramble backwards from the all-off board, dropping the
+    // clicks on Nothing buttons and the ones that cannot be undone
+    fn build(&self, config: &GeneratorConfig) -> (ToggleLevel, Vec<Coordinate>) {
+        let mut level = ToggleLevel::new();
+        level.width = config.width;
+        level.height = config.height;
+        level.topology = config.topology;
+        level.voids = config.voids.clone();
+        level.ray_mode = config.ray_mode;
+        level.subtypes = self.subtypes.clone();
+        level.states = BitArray::new(config.num_cells());
+
+        let mut bit_grid = BitGrid::from_level(&level);
+        let mut scramble = Vec::with_capacity(self.scramble.len());
+        for coord in self.scramble.iter() {
+            if let ButtonFunction::Nothing = bit_grid.func(coord.x, coord.y) {
+                continue;
+            }
+            if bit_grid.unclick(coord.x, coord.y) {
+                scramble.push(*coord);
+            }
+        }
+
+        level.states = bit_grid.get_states();
+        (level, scramble)
+    }
+
+    // Levels whose click bounds already miss the target are scored from the
+    // bounds, only the others are solved
+    fn evaluate(&self, config: &GeneratorConfig, target: &Target) -> Evaluation {
+        let (mut level, scramble) = self.build(config);
+
+        // The reversed scramble solves the level, so it bounds the clicks from above
+        let upper_bound = scramble.len() as u32;
+        let heuristic = Admissible::new(&Grid::from_level(&level));
+        let lower_bound = heuristic.estimate(&BitGrid::from_level(&level)) as u32;
+        if upper_bound < *target.min_clicks.start() {
+            return Evaluation::Bounded(target.distance(upper_bound, target.difficulty_hint()));
+        }
+        if lower_bound > *target.min_clicks.end() {
+            return Evaluation::Bounded(target.distance(lower_bound, target.difficulty_hint()));
+        }
+
+        let solution = match solve_optimal(&level, &config.limits) {
+            Some(solution) => solution,
+            None => return Evaluation::Unsolved,
+        };
+        level.min_clicks = solution.len() as u32;
+
+        // Boards too large for a level code never make it into the output
+        let code = match writer::encode(&level) {
+            Ok(code) => code,
+            Err(_) => return Evaluation::Unsolved,
+        };
+
+        let score = target.distance(level.min_clicks, difficulty::flip_score(&level, &solution));
+        let generated = Generated {
+            code,
+            level,
+            scramble,
+            solution,
+        };
+
+        Evaluation::Solved(Box::new(generated), score)
+    }
+}
+
+enum Evaluation {
+    Solved(Box<Generated>, f64),
+    Bounded(f64),
+    Unsolved,
+}
+
+/// Searches for a solvable level whose optimal clicks and difficulty fall in the
+/// ranges of `target`, starting over from a random level when it gets stuck
+pub fn generate_targeted(config: &GeneratorConfig, target: &Target) -> TargetedOutput {
+    let mut rng = Rng::new(config.seed);
+    let mut current: Option<(Candidate, f64)> = None;
+    let mut stale = 0;
+    let mut candidates = 0;
+
+    while candidates < target.max_candidates {
+        let candidate = match &current {
+            Some((best, _)) if stale < RESTART_AFTER => best.mutate(config, &mut rng),
+            _ => {
+                stale = 0;
+                current = None;
+                Candidate::random(config, &mut rng)
+            }
+        };
+        candidates += 1;
+
+        let score = match candidate.evaluate(config, target) {
+            Evaluation::Solved(generated, 0.0) => {
+                return TargetedOutput {
+                    result: Some(*generated),
+                    candidates,
+                };
+            }
+            Evaluation::Solved(_, score) | Evaluation::Bounded(score) => score,
+            Evaluation::Unsolved => {
+                stale += 1;
+                continue;
+            }
+        };
+
+        // Equal scores are accepted too, so the search can drift across plateaus
+        let best_score = match &current {
+            Some((_, best_score)) => *best_score,
+            None => f64::INFINITY,
+        };
+        if score < best_score {
+            stale = 0;
+        } else {
+            stale += 1;
+        }
+        if score <= best_score {
+            current = Some((candidate, score));
+        }
+    }
+
+    TargetedOutput {
+        result: None,
+        candidates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::reader;
+    use crate::toggle::testing::shortest_solution;
+    use crate::toggle::verify;
+
+    #[test]
+    fn min_clicks_match_breadth_first_search() {
+        for seed in 0..60 {
+            let (width, height) = [(2, 2), (3, 2), (3, 3)][seed as usize % 3];
+            let mut config = GeneratorConfig::new(width, height, seed);
+            config.scramble_clicks = 4;
+            let generated = generate(&config).unwrap();
+
+            let level = &generated.level;
+            assert_eq!(Some(level.min_clicks as usize), shortest_solution(level));
+            assert!(verify::is_solution(level, &generated.solution));
+
+            let undo: Vec<Coordinate> = generated.scramble.iter().rev().copied().collect();
+            assert!(verify::is_solution(level, &undo));
+
+            let parsed = reader::parse(generated.code).unwrap();
+            assert_eq!(parsed.min_clicks, level.min_clicks);
+        }
+    }
+
+    #[test]
+    fn targeted_levels_are_on_target() {
+        let mut config = GeneratorConfig::new(3, 3, 47);
+        config.subtypes = vec![1, 2, 3, 4, 11];
+        let mut target = Target::new(3..=4, 0.0..=1000.0);
+        target.max_candidates = 500;
+
+        let generated = generate_targeted(&config, &target).result.unwrap();
+        let level = &generated.level;
+        assert!(target.min_clicks.contains(&level.min_clicks));
+        assert_eq!(Some(level.min_clicks as usize), shortest_solution(level));
+    }
+}
@@ -0,0 +1,117 @@
+use super::bitgrid::BitGrid;
+use super::core::{Goal, Grid};
+
+/// Estimates the cost left to reach the goal from the current states,
+/// `grid` holds both the states and the goal
+pub trait Heuristic {
+    fn estimate(&self, grid: &BitGrid) -> i32;
+}
+
+pub struct Hamming;
+
+impl Heuristic for Hamming {
+    fn estimate(&self, grid: &BitGrid) -> i32 {
+        grid.remaining() as i32
+    }
+}
+
+pub struct SquaredHamming;
+
+impl Heuristic for SquaredHamming {
+    fn estimate(&self, grid: &BitGrid) -> i32 {
+        let diff = grid.remaining() as i32;
+        diff * diff
+    }
+}
+
+pub struct Weighted {
+    pub heuristic: Box<dyn Heuristic>,
+    pub weight: i32,
+}
+
+impl Weighted {
+    pub fn new(heuristic: Box<dyn Heuristic>, weight: i32) -> Weighted {
+        Weighted { heuristic, weight }
+    }
+}
+
+impl Heuristic for Weighted {
+    fn estimate(&self, grid: &BitGrid) -> i32 {
+        self.weight * self.heuristic.estimate(grid)
+    }
+}
+
+/// No click can change more cared cells than its button affects, so
+/// ceil(diff / max_toggles) clicks are always needed. Never overestimates.
+pub struct Admissible {
+    max_toggles: i32,
+}
+
+impl Admissible {
+    /// Bound for a goal in which every cell is cared about
+    pub fn new(grid: &Grid) -> Admissible {
+        let num_cells = (grid.width() * grid.height()) as usize;
+        Admissible::with_goal(grid, &Goal::new(num_cells))
+    }
+
+    pub fn with_goal(grid: &Grid, goal: &Goal) -> Admissible {
+        let height = grid.height();
+
+        let mut max_toggles = 1;
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let toggles = grid
+                    .affected_cells(x, y)
+                    .iter()
+                    .filter(|cell| goal.care.get((cell.x * height + cell.y) as usize))
+                    .count();
+                max_toggles = max_toggles.max(toggles as i32);
+            }
+        }
+
+        Admissible { max_toggles }
+    }
+}
+
+impl Heuristic for Admissible {
+    fn estimate(&self, grid: &BitGrid) -> i32 {
+        let diff = grid.remaining() as i32;
+        (diff + self.max_toggles - 1) / self.max_toggles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::testing::{distances, random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::{BitArray, Rng};
+
+    #[test]
+    fn admissible_never_overestimates() {
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut rng = Rng::new(29);
+        for (width, height) in [(2, 2), (3, 2), (2, 3)] {
+            for i in 0..20 {
+                let mut level = random_level(width, height, &subtypes, &mut rng);
+                if i % 2 == 1 {
+                    random_goal(&mut level, &mut rng);
+                }
+                let heuristic = Admissible::with_goal(&Grid::from_level(&level), &level.goal());
+
+                let mut grid = BitGrid::from_level(&level);
+                for key in distances(&level).keys() {
+                    grid.set_key(key);
+                    let mut from_state = level.clone();
+                    from_state.states = BitArray::from_key(key, level.num_cells());
+                    if let Some(clicks) = shortest_solution(&from_state) {
+                        assert!(
+                            heuristic.estimate(&grid) <= clicks as i32,
+                            "{:?}",
+                            level.subtypes
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use super::astar::{self, Limit, SolverConfig};
+use super::core::{Coordinate, ToggleLevel};
+use super::linear;
+use super::utils::BitArray;
+use super::verify::{self, InvalidClick};
+
+pub struct Hint {
+    /// Next click on a minimum-click path from the current state
+    pub click: Coordinate,
+    /// Clicks left to clear the board, including `click`
+    pub remaining: usize,
+    /// The whole path, starting with `click`
+    pub solution: Vec<Coordinate>,
+}
+
+pub enum Result {
+    Next(Hint),
+    /// The board already matches the goal
+    Cleared,
+    /// No clicks clear the board from the current state
+    Unsolvable,
+    /// The search hit one of the limits of its config
+    Aborted(Limit),
+    /// A click of the history could not be replayed
+    InvalidClick(InvalidClick),
+}
+
+/// Best next click from `states`, which must hold one bit per cell
+/// in the same column-major order as the level's states.
+/// Searches at most astar::DEFAULT_MAX_NODES nodes.
+pub fn hint(level: &ToggleLevel, states: &BitArray) -> Result {
+    let mut config = SolverConfig::optimal(level);
+    config.limits.max_nodes = Some(astar::DEFAULT_MAX_NODES);
+    hint_with(level, states, &config)
+}
+
+/// Like `hint`, searching levels that are not linear with `config`.
+/// The hint is only optimal if the config is.
+pub fn hint_with(level: &ToggleLevel, states: &BitArray, config: &SolverConfig) -> Result {
+    if level.goal().is_reached(states) {
+        return Result::Cleared;
+    }
+
+    let mut current = level.clone();
+    current.states = states.clone();
+
+    let solution = match linear::solve_linear(&current) {
+        linear::Result::Success(orders) => orders,
+        linear::Result::Fail => return Result::Unsolvable,
+        // The search for the minimum ran out of nodes, A* would not get further
+        linear::Result::Approximate(_) => return Result::Aborted(Limit::Nodes),
+        linear::Result::NotLinear => match astar::run_astar_from(level, states, config).result {
+            astar::Result::Success(orders) => orders,
+            astar::Result::Fail => return Result::Unsolvable,
+            astar::Result::Aborted(partial) => return Result::Aborted(partial.reason),
+        },
+    };
+
+    Result::Next(Hint {
+        click: solution[0],
+        remaining: solution.len(),
+        solution,
+    })
+}
+
+/// Best next click after the player clicked `history` from the level's start
+pub fn hint_after(level: &ToggleLevel, history: &[Coordinate]) -> Result {
+    let replay = verify::replay(level, history);
+    if let Some(invalid) = replay.invalid {
+        return Result::InvalidClick(invalid);
+    }
+
+    hint(level, replay.final_states())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::bitgrid::BitGrid;
+    use crate::toggle::testing::{distances, random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::Rng;
+
+    #[test]
+    fn remaining_matches_breadth_first_search() {
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut rng = Rng::new(71);
+        for i in 0..60 {
+            let mut level = random_level(3, 2, &subtypes, &mut rng);
+            if i % 2 == 1 {
+                random_goal(&mut level, &mut rng);
+            }
+
+            // Hints from a state the player can reach, not only from the start
+            let mut current = level.clone();
+            let reachable: Vec<_> = distances(&level).into_keys().collect();
+            let mut grid = BitGrid::from_level(&level);
+            grid.set_key(&reachable[rng.below(reachable.len())]);
+            current.states = grid.get_states();
+
+            let clicks = shortest_solution(&current);
+            match hint(&level, &current.states) {
+                Result::Next(hint) => {
+                    assert_eq!(Some(hint.remaining), clicks);
+                    assert_eq!(hint.click, hint.solution[0]);
+                    assert!(verify::is_solution(&current, &hint.solution));
+                }
+                Result::Cleared => assert_eq!(clicks, Some(0)),
+                Result::Unsolvable => assert_eq!(clicks, None),
+                _ => panic!("small boards are solved within the limits"),
+            }
+        }
+    }
+
+    #[test]
+    fn node_limit_aborts_the_hint() {
+        let mut rng = Rng::new(73);
+        let level = random_level(5, 5, &[14, 15, 16, 17], &mut rng);
+
+        let mut config = SolverConfig::optimal(&level);
+        config.limits.max_nodes = Some(10);
+        assert!(matches!(
+            hint_with(&level, &level.states, &config),
+            Result::Aborted(Limit::Nodes)
+        ));
+    }
+
+    #[test]
+    fn invalid_history_is_reported() {
+        let mut rng = Rng::new(79);
+        let level = random_level(2, 2, &[11], &mut rng);
+        let history = [Coordinate::new(0, 0), Coordinate::new(5, 0)];
+        match hint_after(&level, &history) {
+            Result::InvalidClick(invalid) => assert_eq!(invalid.index, 1),
+            _ => panic!("the second click is out of range"),
+        }
+    }
+}
@@ -0,0 +1,902 @@
+use super::bitgrid::{get_bit, to_words};
+use super::core::{ButtonFunction, Coordinate, Grid, ToggleLevel};
+use super::utils::BitArray;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+pub enum Result {
+    Success(Vec<Coordinate>),
+    /// Best solution found before the search for the minimum gave up, it may not be minimal
+    Approximate(Vec<Coordinate>),
+    Fail,
+    NotLinear,
+}
+
+//
+// Linear System over GF(2)
+//
+// One row per cared cell. Bits 0..buttons.len() hold which buttons toggle the
+// cell, the last bit tells whether the cell's initial state differs from the goal.
+//
+struct LinearSystem {
+    buttons: Vec<Coordinate>,
+    rows: Vec<BitArray>,
+    // Column-major index of the cell behind every row, in the initial order
+    cells: Vec<usize>,
+    pivots: Vec<usize>,
+}
+
+impl LinearSystem {
+    fn from_level(level: &ToggleLevel) -> Option<LinearSystem> {
+        let mut grid = Grid::from_level(level);
+        let initial_state = grid.get_states();
+        let goal = level.goal();
+
+        let mut buttons = Vec::new();
+        let mut effects = Vec::new();
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let func = &grid.at(x, y).unwrap().func;
+                if !func.is_linear() {
+                    return None;
+                }
+
+                if let ButtonFunction::Nothing = func {
+                    continue;
+                }
+
+                grid.set_all_state(false);
+                grid.click(x, y);
+
+                buttons.push(Coordinate::new(x, y));
+                effects.push(grid.get_states());
+            }
+        }
+
+        let num_columns = buttons.len();
+        let cells: Vec<usize> = (0..initial_state.len())
+            .filter(|&cell| goal.care.get(cell))
+            .collect();
+        let mut rows = Vec::with_capacity(cells.len());
+        for &cell in cells.iter() {
+            let mut row = BitArray::new(num_columns + 1);
+            for (column, effect) in effects.iter().enumerate() {
+                row.set(column, effect.get(cell));
+            }
+            let is_off_goal = initial_state.get(cell) != goal.target.get(cell);
+            row.set(num_columns, is_off_goal);
+            rows.push(row);
+        }
+
+        Some(LinearSystem {
+            buttons,
+            rows,
+            cells,
+            pivots: Vec::new(),
+        })
+    }
+
+    fn num_columns(&self) -> usize {
+        self.buttons.len()
+    }
+
+    // Gauss-Jordan elimination into reduced row echelon form
+    fn eliminate(&mut self) {
+        let mut rank = 0;
+
+        for column in 0..self.num_columns() {
+            let found = (rank..self.rows.len()).find(|&r| self.rows[r].get(column));
+            let pivot_index = match found {
+                Some(r) => r,
+                None => continue,
+            };
+
+            self.rows.swap(rank, pivot_index);
+
+            let pivot_row = self.rows[rank].clone();
+            for r in 0..self.rows.len() {
+                if r != rank && self.rows[r].get(column) {
+                    self.rows[r].xor(&pivot_row);
+                }
+            }
+
+            self.pivots.push(column);
+            rank += 1;
+        }
+    }
+
+    fn is_consistent(&self) -> bool {
+        let augmented = self.num_columns();
+        self.rows[self.pivots.len()..]
+            .iter()
+            .all(|row| !row.get(augmented))
+    }
+
+    // Solution with every free variable set to zero
+    fn particular_solution(&self) -> BitArray {
+        let augmented = self.num_columns();
+        let mut result = BitArray::new(augmented);
+
+        for (i, &pivot) in self.pivots.iter().enumerate() {
+            result.set(pivot, self.rows[i].get(augmented));
+        }
+
+        result
+    }
+
+    fn null_space(&self) -> Vec<BitArray> {
+        let mut is_pivot = vec![false; self.num_columns()];
+        for &pivot in self.pivots.iter() {
+            is_pivot[pivot] = true;
+        }
+
+        let mut result = Vec::new();
+        for (free, _) in is_pivot.iter().enumerate().filter(|(_, &p)| !p) {
+            let mut vector = BitArray::new(self.num_columns());
+            vector.set(free, true);
+            for (i, &pivot) in self.pivots.iter().enumerate() {
+                if self.rows[i].get(free) {
+                    vector.set(pivot, true);
+                }
+            }
+            result.push(vector);
+        }
+
+        result
+    }
+
+    // Appends one bit per row to every row, so after elimination each row
+    // also tells which original rows were added up to form it
+    fn track_rows(&mut self) {
+        let num_bits = self.num_columns() + 1;
+        let num_rows = self.rows.len();
+
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            let mut tracked = BitArray::new(num_bits + num_rows);
+            for bit in 0..num_bits {
+                tracked.set(bit, row.get(bit));
+            }
+            tracked.set(num_bits + i, true);
+            *row = tracked;
+        }
+    }
+
+    // Which rows a column toggles
+    fn column(&self, column: usize) -> BitArray {
+        let mut result = BitArray::new(self.rows.len());
+        for (r, row) in self.rows.iter().enumerate() {
+            result.set(r, row.get(column));
+        }
+        result
+    }
+
+    // The system made of some rows and columns, in the given order
+    fn subsystem(&self, rows: &[usize], columns: &[usize]) -> LinearSystem {
+        let augmented = self.num_columns();
+        let mut result_rows = Vec::with_capacity(rows.len());
+        for &r in rows.iter() {
+            let mut row = BitArray::new(columns.len() + 1);
+            for (i, &column) in columns.iter().enumerate() {
+                row.set(i, self.rows[r].get(column));
+            }
+            row.set(columns.len(), self.rows[r].get(augmented));
+            result_rows.push(row);
+        }
+
+        LinearSystem {
+            buttons: columns.iter().map(|&c| self.buttons[c]).collect(),
+            rows: result_rows,
+            cells: rows.iter().map(|&r| self.cells[r]).collect(),
+            pivots: Vec::new(),
+        }
+    }
+
+    fn to_orders(&self, solution: &BitArray) -> Vec<Coordinate> {
+        let mut result = Vec::new();
+        for (i, coord) in self.buttons.iter().enumerate() {
+            if solution.get(i) {
+                result.push(*coord);
+            }
+        }
+        result
+    }
+}
+
+//
+// Independent Parts
+//
+// Buttons that toggle the same cared cells are interchangeable, a minimum
+// solution clicks at most one of them. Groups of buttons that share no cell
+// are solved on their own. Both keep the null space of every part small.
+//
+struct Part {
+    system: LinearSystem,
+    // Number of buttons with the same effect as each column
+    copies: Vec<u64>,
+}
+
+impl Part {
+    // Particular solution and null space, None if the part cannot be solved
+    fn solutions(&mut self) -> Option<(BitArray, Vec<BitArray>)> {
+        self.system.eliminate();
+        if !self.system.is_consistent() {
+            return None;
+        }
+        Some((self.system.particular_solution(), self.system.null_space()))
+    }
+}
+
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+// Splits an uneliminated system. Cells no button toggles end up in a last
+// part without columns, which is unsolvable if any of them is off its goal.
+fn split(system: &LinearSystem) -> Vec<Part> {
+    let num_rows = system.rows.len();
+
+    let mut columns = Vec::new();
+    let mut copies = Vec::new();
+    let mut effects: Vec<BitArray> = Vec::new();
+    let mut seen = HashMap::new();
+    for column in 0..system.num_columns() {
+        let effect = system.column(column);
+        if effect.count_ones() == 0 {
+            continue;
+        }
+        match seen.entry(effect.bytes().to_vec()) {
+            Entry::Occupied(entry) => copies[*entry.get()] += 1,
+            Entry::Vacant(entry) => {
+                entry.insert(columns.len());
+                columns.push(column);
+                copies.push(1);
+                effects.push(effect);
+            }
+        }
+    }
+
+    // Joins the columns that toggle a common row
+    let mut parent: Vec<usize> = (0..columns.len()).collect();
+    let mut owner: Vec<Option<usize>> = vec![None; num_rows];
+    for (i, effect) in effects.iter().enumerate() {
+        for (row, row_owner) in owner.iter_mut().enumerate() {
+            if !effect.get(row) {
+                continue;
+            }
+            match *row_owner {
+                Some(other) => {
+                    let root = find_root(&mut parent, i);
+                    parent[root] = find_root(&mut parent, other);
+                }
+                None => *row_owner = Some(i),
+            }
+        }
+    }
+
+    let mut group_of = vec![usize::MAX; columns.len()];
+    let mut groups: Vec<(Vec<usize>, Vec<usize>)> = Vec::new();
+    for i in 0..columns.len() {
+        let root = find_root(&mut parent, i);
+        if group_of[root] == usize::MAX {
+            group_of[root] = groups.len();
+            groups.push((Vec::new(), Vec::new()));
+        }
+        groups[group_of[root]].1.push(i);
+    }
+
+    let mut unreached = Vec::new();
+    for (row, row_owner) in owner.iter().enumerate() {
+        match row_owner {
+            Some(i) => groups[group_of[find_root(&mut parent, *i)]].0.push(row),
+            None => unreached.push(row),
+        }
+    }
+
+    let mut result: Vec<Part> = groups
+        .into_iter()
+        .map(|(rows, members)| {
+            let part_columns: Vec<usize> = members.iter().map(|&i| columns[i]).collect();
+            Part {
+                system: system.subsystem(&rows, &part_columns),
+                copies: members.iter().map(|&i| copies[i]).collect(),
+            }
+        })
+        .collect();
+    if !unreached.is_empty() {
+        result.push(Part {
+            system: system.subsystem(&unreached, &[]),
+            copies: Vec::new(),
+        });
+    }
+    result
+}
+
+//
+// Minimum Search
+//
+// Branch and bound over the null space. Its vectors are first shortened, then
+// split into small blocks that share no button and a few global vectors that
+// join the blocks together. The search branches on the global vectors only.
+// A button is settled once no remaining global vector can flip it, and trying
+// every combination of a block over its settled buttons gives the lower bound.
+//
+const MAX_BLOCK_SIZE: usize = 8;
+// Nodes visited before falling back to the best solution found so far
+const MAX_SEARCH_NODES: u64 = 100_000;
+
+fn count_words(words: &[u64]) -> u32 {
+    words.iter().map(|w| w.count_ones()).sum()
+}
+
+fn xor_words(a: &mut [u64], b: &[u64]) {
+    for (x, y) in a.iter_mut().zip(b.iter()) {
+        *x ^= *y;
+    }
+}
+
+fn overlaps(a: &[u64], b: &[u64]) -> bool {
+    a.iter().zip(b.iter()).any(|(x, y)| x & y != 0)
+}
+
+// First and last set bit
+fn span(words: &[u64]) -> (usize, usize) {
+    let first = words.iter().position(|&w| w != 0).unwrap_or(0);
+    let last = words.iter().rposition(|&w| w != 0).unwrap_or(0);
+    (
+        first * 64 + words[first].trailing_zeros() as usize,
+        last * 64 + 63 - words[last].leading_zeros() as usize,
+    )
+}
+
+// Adds vectors to each other until no two start or end at the same button.
+// Every addition strictly shortens one span, so this ends.
+fn shorten(vectors: &mut [Vec<u64>]) {
+    let mut spans: Vec<(usize, usize)> = vectors.iter().map(|v| span(v)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..vectors.len() {
+            for j in 0..vectors.len() {
+                let ((start_i, end_i), (start_j, end_j)) = (spans[i], spans[j]);
+                let is_longer = (start_i == start_j && end_j >= end_i)
+                    || (end_i == end_j && start_j <= start_i);
+                if i == j || !is_longer {
+                    continue;
+                }
+                let other = vectors[i].clone();
+                xor_words(&mut vectors[j], &other);
+                spans[j] = span(&vectors[j]);
+                changed = true;
+            }
+        }
+    }
+}
+
+// Groups of vectors joined by shared buttons
+fn overlap_groups(vectors: &[Vec<u64>]) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..vectors.len()).collect();
+    for i in 0..vectors.len() {
+        for j in (i + 1)..vectors.len() {
+            if overlaps(&vectors[i], &vectors[j]) {
+                let root = find_root(&mut parent, i);
+                parent[root] = find_root(&mut parent, j);
+            }
+        }
+    }
+
+    let mut group_of = vec![usize::MAX; vectors.len()];
+    let mut result: Vec<Vec<usize>> = Vec::new();
+    for i in 0..vectors.len() {
+        let root = find_root(&mut parent, i);
+        if group_of[root] == usize::MAX {
+            group_of[root] = result.len();
+            result.push(Vec::new());
+        }
+        result[group_of[root]].push(i);
+    }
+    result
+}
+
+struct Block {
+    support: Vec<u64>,
+    // Range of words the block touches
+    first_word: usize,
+    last_word: usize,
+    combinations: Vec<Vec<u64>>,
+}
+
+impl Block {
+    fn new(vectors: &[Vec<u64>]) -> Block {
+        let mut support = vec![0; vectors[0].len()];
+        let mut combinations = vec![vec![0; support.len()]];
+        for vector in vectors.iter() {
+            for (s, v) in support.iter_mut().zip(vector.iter()) {
+                *s |= *v;
+            }
+            for i in 0..combinations.len() {
+                let mut combination = combinations[i].clone();
+                xor_words(&mut combination, vector);
+                combinations.push(combination);
+            }
+        }
+
+        let (start, end) = span(&support);
+        Block {
+            support,
+            first_word: start / 64,
+            last_word: end / 64,
+            combinations,
+        }
+    }
+
+    // Fewest settled buttons of the block left clicked by any combination
+    fn cheapest(&self, current: &[u64], settled: &[u64]) -> (u32, usize) {
+        let words = self.first_word..=self.last_word;
+        let mut result = (u32::MAX, 0);
+        for (i, combination) in self.combinations.iter().enumerate() {
+            let clicks = words
+                .clone()
+                .map(|w| {
+                    ((current[w] ^ combination[w]) & settled[w] & self.support[w]).count_ones()
+                })
+                .sum();
+            if clicks < result.0 {
+                result = (clicks, i);
+            }
+        }
+        result
+    }
+}
+
+struct MinimumSearch {
+    global: Vec<Vec<u64>>,
+    blocks: Vec<Block>,
+    // Buttons outside every block
+    free: Vec<u64>,
+    // settled[d]: buttons no global vector from index d on can flip
+    settled: Vec<Vec<u64>>,
+    // touched[d]: blocks that global vector d overlaps
+    touched: Vec<Vec<usize>>,
+    best: Vec<u64>,
+    best_clicks: u32,
+    nodes: u64,
+}
+
+impl MinimumSearch {
+    fn new(particular: Vec<u64>, mut vectors: Vec<Vec<u64>>) -> MinimumSearch {
+        shorten(&mut vectors);
+
+        // Starts from greedily adding vectors while that removes clicks
+        let mut best = particular;
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for vector in vectors.iter() {
+                let mut candidate = best.clone();
+                xor_words(&mut candidate, vector);
+                if count_words(&candidate) < count_words(&best) {
+                    best = candidate;
+                    improved = true;
+                }
+            }
+        }
+
+        // Moves the vector that overlaps most others out of the largest group
+        // until every group is small enough to try all its combinations
+        let mut global = Vec::new();
+        let groups = loop {
+            let groups = overlap_groups(&vectors);
+            let largest = match groups.iter().max_by_key(|group| group.len()) {
+                Some(group) if group.len() > MAX_BLOCK_SIZE => group,
+                _ => break groups,
+            };
+            let &most_overlapping = largest
+                .iter()
+                .max_by_key(|&&i| {
+                    let others = largest.iter().filter(|&&j| j != i);
+                    others
+                        .filter(|&&j| overlaps(&vectors[i], &vectors[j]))
+                        .count()
+                })
+                .unwrap();
+            global.push(vectors.remove(most_overlapping));
+        };
+
+        let num_words = best.len();
+        let blocks: Vec<Block> = groups
+            .iter()
+            .map(|group| {
+                let members: Vec<Vec<u64>> = group.iter().map(|&i| vectors[i].clone()).collect();
+                Block::new(&members)
+            })
+            .collect();
+
+        let mut free = vec![!0; num_words];
+        for block in blocks.iter() {
+            for (f, s) in free.iter_mut().zip(block.support.iter()) {
+                *f &= !s;
+            }
+        }
+
+        let mut settled = vec![vec![!0; num_words]; global.len() + 1];
+        let mut flippable = vec![0; num_words];
+        for depth in (0..global.len()).rev() {
+            for (f, g) in flippable.iter_mut().zip(global[depth].iter()) {
+                *f |= *g;
+            }
+            for (s, f) in settled[depth].iter_mut().zip(flippable.iter()) {
+                *s = !f;
+            }
+        }
+
+        let touched = global
+            .iter()
+            .map(|vector| {
+                let overlapping =
+                    (0..blocks.len()).filter(|&b| overlaps(vector, &blocks[b].support));
+                overlapping.collect()
+            })
+            .collect();
+
+        MinimumSearch {
+            global,
+            blocks,
+            free,
+            settled,
+            touched,
+            best_clicks: count_words(&best),
+            best,
+            nodes: 0,
+        }
+    }
+
+    // Clicks that stay whatever the remaining global vectors are set to,
+    // exact once they are all set. `costs` holds the cheapest clicks of each block.
+    fn lower_bound(&self, current: &[u64], depth: usize, costs: &[u32]) -> u32 {
+        let settled = &self.settled[depth];
+        let outside: u32 = current
+            .iter()
+            .zip(settled.iter().zip(self.free.iter()))
+            .map(|(c, (s, f))| (c & s & f).count_ones())
+            .sum();
+        outside + costs.iter().sum::<u32>()
+    }
+
+    fn search(&mut self, current: &mut Vec<u64>, depth: usize, costs: Vec<u32>) {
+        if self.nodes >= MAX_SEARCH_NODES {
+            return;
+        }
+        self.nodes += 1;
+
+        let bound = self.lower_bound(current, depth, &costs);
+        if bound >= self.best_clicks {
+            return;
+        }
+
+        if depth == self.global.len() {
+            let mut best = current.clone();
+            let settled = &self.settled[depth];
+            for block in self.blocks.iter() {
+                let (_, i) = block.cheapest(current, settled);
+                xor_words(&mut best, &block.combinations[i]);
+            }
+            self.best = best;
+            self.best_clicks = bound;
+            return;
+        }
+
+        // Tries first the side that clicks fewer of the buttons the vector flips
+        let vector = self.global[depth].clone();
+        let clicked: u32 = current
+            .iter()
+            .zip(vector.iter())
+            .map(|(c, v)| (c & v).count_ones())
+            .sum();
+        let flip_first = 2 * clicked > count_words(&vector);
+        for flip in [flip_first, !flip_first] {
+            if flip {
+                xor_words(current, &vector);
+            }
+
+            // Only the blocks the vector overlaps can change their cost
+            let mut child_costs = costs.clone();
+            for &b in self.touched[depth].iter() {
+                let settled = &self.settled[depth + 1];
+                child_costs[b] = self.blocks[b].cheapest(current, settled).0;
+            }
+            self.search(current, depth + 1, child_costs);
+
+            if flip {
+                xor_words(current, &vector);
+            }
+        }
+    }
+}
+
+// Solution with the fewest clicks, and whether the search finished.
+// If it ran out of nodes the solution is the best one found.
+fn find_minimum(particular: &BitArray, null_space: &[BitArray]) -> (BitArray, bool) {
+    let num_bits = particular.len();
+    let mut search = MinimumSearch::new(
+        to_words(particular, num_bits),
+        null_space.iter().map(|v| to_words(v, num_bits)).collect(),
+    );
+    let mut current = to_words(particular, num_bits);
+    let costs = search
+        .blocks
+        .iter()
+        .map(|block| block.cheapest(&current, &search.settled[0]).0)
+        .collect();
+    search.search(&mut current, 0, costs);
+
+    let mut result = BitArray::new(num_bits);
+    for i in 0..num_bits {
+        result.set(i, get_bit(&search.best, i));
+    }
+    (result, search.nodes < MAX_SEARCH_NODES)
+}
+
+// Largest null space whose 2^(nullity) solutions are all walked when counting
+const MAX_COUNTED_NULLITY: usize = 20;
+
+// Walks every solution with a Gray code, so each step costs one XOR, and counts
+// those with the fewest clicks. Each stands for one choice among the copies of
+// every button it clicks. Returns None if the null space is too large to walk.
+fn count_minimum(particular: BitArray, null_space: &[BitArray], copies: &[u64]) -> Option<u64> {
+    if null_space.len() > MAX_COUNTED_NULLITY {
+        return None;
+    }
+
+    let choices = |solution: &BitArray| {
+        (0..copies.len())
+            .filter(|&i| solution.get(i))
+            .fold(1u64, |acc, i| acc.saturating_mul(copies[i]))
+    };
+
+    let mut best_clicks = particular.count_ones();
+    let mut best_count = choices(&particular);
+    let mut current = particular;
+
+    for i in 1..(1u64 << null_space.len()) {
+        let flipped = i.trailing_zeros() as usize;
+        current.xor(&null_space[flipped]);
+
+        let clicks = current.count_ones();
+        if clicks < best_clicks {
+            best_clicks = clicks;
+            best_count = choices(&current);
+        } else if clicks == best_clicks {
+            best_count = best_count.saturating_add(choices(&current));
+        }
+    }
+
+    Some(best_count)
+}
+
+/// Solves levels made only of XOR buttons exactly, returning a minimum-click solution.
+/// Returns Result::NotLinear if the level contains buttons that move lights, like Rotate.
+/// If the search for the minimum runs out of nodes, which takes boards far larger than
+/// hand-made ones, the best solution found is returned as Result::Approximate.
+pub fn solve_linear(level: &ToggleLevel) -> Result {
+    let system = match LinearSystem::from_level(level) {
+        Some(system) => system,
+        None => return Result::NotLinear,
+    };
+
+    let mut orders = Vec::new();
+    let mut is_exact = true;
+    for mut part in split(&system) {
+        let (particular, null_space) = match part.solutions() {
+            Some(solutions) => solutions,
+            None => return Result::Fail,
+        };
+
+        let (best, is_minimum) = find_minimum(&particular, &null_space);
+        orders.extend(part.system.to_orders(&best));
+        is_exact &= is_minimum;
+    }
+
+    orders.sort_by_key(|coord| (coord.y, coord.x));
+    if is_exact {
+        Result::Success(orders)
+    } else {
+        Result::Approximate(orders)
+    }
+}
+
+/// Number of distinct sets of buttons that clear the level with the minimum number of clicks.
+/// Returns None if the level is not linear or has too many redundant buttons to count,
+/// and Some(0) if it cannot be solved.
+pub fn count_minimum_solutions(level: &ToggleLevel) -> Option<u64> {
+    let system = LinearSystem::from_level(level)?;
+
+    let mut parts = Vec::new();
+    for mut part in split(&system) {
+        match part.solutions() {
+            Some(solutions) => parts.push((part.copies, solutions)),
+            None => return Some(0),
+        }
+    }
+
+    let mut result: u64 = 1;
+    for (copies, (particular, null_space)) in parts {
+        result = result.saturating_mul(count_minimum(particular, &null_space, &copies)?);
+    }
+    Some(result)
+}
+
+/// Cells in which every button toggles an even number of cells while an odd number
+/// are off their goal. No sequence of clicks changes that parity, so the level cannot
+/// be solved. Returns None if the level is solvable or not linear.
+pub fn parity_witness(level: &ToggleLevel) -> Option<Vec<Coordinate>> {
+    let mut system = LinearSystem::from_level(level)?;
+
+    system.track_rows();
+    system.eliminate();
+
+    let augmented = system.num_columns();
+    let row = system.rows[system.pivots.len()..]
+        .iter()
+        .find(|row| row.get(augmented))?;
+
+    let height = level.height as i32;
+    let mut result = Vec::new();
+    for (i, &cell) in system.cells.iter().enumerate() {
+        if row.get(augmented + 1 + i) {
+            let cell = cell as i32;
+            result.push(Coordinate::new(cell / height, cell % height));
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::core::Topology;
+    use crate::toggle::testing::{
+        random_goal, random_level, scrambled_level, shortest_solution, LINEAR_SUBTYPES,
+    };
+    use crate::toggle::utils::Rng;
+
+    fn clicked_states(level: &ToggleLevel, orders: &[Coordinate]) -> BitArray {
+        let mut grid = Grid::from_level(level);
+        for order in orders.iter() {
+            grid.click(order.x, order.y);
+        }
+        grid.get_states()
+    }
+
+    #[test]
+    fn minimum_matches_breadth_first_search() {
+        let mut rng = Rng::new(3);
+        for (width, height) in [(1, 3), (2, 2), (3, 2), (3, 3), (4, 3)] {
+            for i in 0..40 {
+                let mut level = random_level(width, height, &LINEAR_SUBTYPES, &mut rng);
+                if i % 2 == 1 {
+                    random_goal(&mut level, &mut rng);
+                }
+                if i % 4 == 3 {
+                    level.topology = Topology::Torus;
+                }
+
+                match (solve_linear(&level), shortest_solution(&level)) {
+                    (Result::Success(orders), Some(clicks)) => {
+                        assert_eq!(orders.len(), clicks, "{:?}", level.subtypes);
+                        assert!(level.goal().is_reached(&clicked_states(&level, &orders)));
+                    }
+                    (Result::Fail, None) => {}
+                    (_, clicks) => {
+                        panic!("{:?} has shortest solution {:?}", level.subtypes, clicks)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn count_matches_every_set_of_buttons() {
+        let mut rng = Rng::new(5);
+        for (width, height) in [(2, 2), (3, 2), (3, 3)] {
+            for i in 0..40 {
+                let mut level = random_level(width, height, &LINEAR_SUBTYPES, &mut rng);
+                if i % 2 == 1 {
+                    random_goal(&mut level, &mut rng);
+                }
+
+                let buttons: Vec<Coordinate> = (0..height as i32)
+                    .flat_map(|y| (0..width as i32).map(move |x| Coordinate::new(x, y)))
+                    .filter(|c| level.subtype(c.x as u32, c.y as u32) != 0)
+                    .collect();
+                let mut fewest = usize::MAX;
+                let mut count = 0;
+                for set in 0..(1u32 << buttons.len()) {
+                    let orders: Vec<Coordinate> = (0..buttons.len())
+                        .filter(|&i| set & (1 << i) != 0)
+                        .map(|i| buttons[i])
+                        .collect();
+                    if !level.goal().is_reached(&clicked_states(&level, &orders)) {
+                        continue;
+                    }
+                    if orders.len() < fewest {
+                        fewest = orders.len();
+                        count = 0;
+                    }
+                    if orders.len() == fewest {
+                        count += 1;
+                    }
+                }
+
+                assert_eq!(
+                    count_minimum_solutions(&level),
+                    Some(count),
+                    "{:?}",
+                    level.subtypes
+                );
+            }
+        }
+    }
+
+    // Fewest clicks over every solution, without splitting the system
+    fn walked_minimum(level: &ToggleLevel) -> Option<u32> {
+        let mut system = LinearSystem::from_level(level).unwrap();
+        system.eliminate();
+        let null_space = system.null_space();
+        if null_space.len() > MAX_COUNTED_NULLITY {
+            return None;
+        }
+
+        let mut current = system.particular_solution();
+        let mut result = current.count_ones();
+        for i in 1..(1u64 << null_space.len()) {
+            current.xor(&null_space[i.trailing_zeros() as usize]);
+            result = result.min(current.count_ones());
+        }
+        Some(result)
+    }
+
+    #[test]
+    fn minimum_matches_walking_the_null_space() {
+        let sets: [&[u8]; 4] = [&[1, 2, 3, 4], &[5, 6, 7, 8], &[20], &[24]];
+        let mut rng = Rng::new(13);
+        let mut num_walked = 0;
+        for subtypes in sets {
+            for size in 5..=9 {
+                let level = scrambled_level(size, size, subtypes, 40, &mut rng);
+                let (clicks, result) = match (walked_minimum(&level), solve_linear(&level)) {
+                    (Some(clicks), Result::Success(orders)) => (clicks, orders),
+                    (None, _) => continue,
+                    _ => panic!("{:?} was not solved exactly", level.subtypes),
+                };
+                assert_eq!(result.len(), clicks as usize, "{:?}", level.subtypes);
+                num_walked += 1;
+            }
+        }
+        assert!(num_walked >= 10);
+    }
+
+    #[test]
+    fn large_boards_are_solved_exactly() {
+        let sets: [&[u8]; 4] = [
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13],
+            &[1, 2, 3, 4],
+            &[20],
+            &[24],
+        ];
+        let mut rng = Rng::new(9);
+        for subtypes in sets {
+            let level = scrambled_level(20, 20, subtypes, 300, &mut rng);
+
+            match solve_linear(&level) {
+                Result::Success(orders) => {
+                    assert!(level.goal().is_reached(&clicked_states(&level, &orders)));
+                }
+                _ => panic!("{:?} was not solved exactly", subtypes),
+            }
+        }
+    }
+}
@@ -1,4 +1,19 @@
+//! Puzzle model, level codec and solvers.
+
+pub mod analysis;
+pub mod astar;
+pub mod behavior;
+pub mod bitgrid;
 pub mod core;
+pub mod difficulty;
+pub mod generator;
+pub mod heuristic;
+pub mod hint;
+pub mod linear;
 pub mod reader;
+#[cfg(test)]
+mod testing;
+pub mod uniqueness;
 pub mod utils;
-pub mod astar;
\ No newline at end of file
+pub mod verify;
+pub mod writer;
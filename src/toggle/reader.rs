@@ -1,88 +1,310 @@
 use flate2::read::DeflateDecoder;
 
-use std::io::Read;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
 
-use crate::toggle::{core::ToggleLevel, utils::BitArray};
+use crate::toggle::core::{Goal, RawSection, RayMode, ToggleLevel, Topology};
+use crate::toggle::utils::BitArray;
 
-pub fn parse(text: String) -> ToggleLevel {
+pub const WIDTH_DEF: u8 = 0x01;
+pub const HEIGHT_DEF: u8 = 0x02;
+pub const SUBTYPES_DEF: u8 = 0x03;
+pub const STATES_DEF: u8 = 0x04;
+pub const MINIMUM_CLICK_DEF: u8 = 0x05;
+pub const CREATOR_DEF: u8 = 0x06;
+pub const GOAL_DEF: u8 = 0x07;
+pub const CARE_DEF: u8 = 0x08;
+pub const TOPOLOGY_DEF: u8 = 0x09;
+pub const VOID_DEF: u8 = 0x0a;
+
+// String lengths take at most 5 bytes, enough for any u32
+const MAX_LENGTH_BYTES: usize = 5;
+
+#[derive(Debug)]
+pub enum ParseError {
+    InvalidBase64(base64::DecodeError),
+    InvalidDeflate(io::Error),
+    // A section needs more bytes than are left
+    Truncated {
+        tag: u8,
+        offset: usize,
+        needed: usize,
+    },
+    // Width or height is absent or zero where the button count is needed
+    MissingDimensions {
+        offset: usize,
+    },
+    // Subtypes, states or goal do not cover width * height buttons
+    LengthMismatch {
+        tag: u8,
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+    NegativeMinClicks {
+        offset: usize,
+        value: i32,
+    },
+    UnknownTopology {
+        offset: usize,
+        value: u8,
+    },
+    UnknownRayMode {
+        offset: usize,
+        value: u8,
+    },
+    // Only reported by the strict parsers, the others keep the section
+    UnknownTag {
+        offset: usize,
+        tag: u8,
+    },
+    // A string length takes more than 5 bytes
+    InvalidLength {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidBase64(e) => write!(f, "invalid base64: {}", e),
+            ParseError::InvalidDeflate(e) => write!(f, "invalid deflate stream: {}", e),
+            ParseError::Truncated {
+                tag,
+                offset,
+                needed,
+            } => write!(
+                f,
+                "section 0x{:02x} at byte {} needs {} more bytes",
+                tag, offset, needed
+            ),
+            ParseError::MissingDimensions { offset } => {
+                write!(f, "width or height missing before byte {}", offset)
+            }
+            ParseError::LengthMismatch {
+                tag,
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "section 0x{:02x} at byte {} covers {} buttons, expected {}",
+                tag, offset, found, expected
+            ),
+            ParseError::NegativeMinClicks { offset, value } => {
+                write!(f, "negative min_clicks {} at byte {}", value, offset)
+            }
+            ParseError::UnknownTopology { offset, value } => {
+                write!(f, "unknown topology {} at byte {}", value, offset)
+            }
+            ParseError::UnknownRayMode { offset, value } => {
+                write!(f, "unknown ray mode {} at byte {}", value, offset)
+            }
+            ParseError::UnknownTag { offset, tag } => {
+                write!(f, "unknown tag 0x{:02x} at byte {}", tag, offset)
+            }
+            ParseError::InvalidLength { offset } => {
+                write!(f, "invalid string length at byte {}", offset)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::InvalidBase64(e) => Some(e),
+            ParseError::InvalidDeflate(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a base64 level code
+pub fn parse(text: String) -> Result<ToggleLevel, ParseError> {
+    parse_toggle(&inflate(&text)?)
+}
+
+/// Decodes a base64 level code, failing on tags this reader does not know
+pub fn parse_strict(text: String) -> Result<ToggleLevel, ParseError> {
+    parse_toggle_strict(&inflate(&text)?)
+}
+
+fn inflate(text: &str) -> Result<Vec<u8>, ParseError> {
     let text_trim = text.trim();
-    let comp_input = base64::decode(text_trim).unwrap();
+    let comp_input = base64::decode(text_trim).map_err(ParseError::InvalidBase64)?;
 
     let mut comp_output: Vec<u8> = Vec::new();
     let mut deflater = DeflateDecoder::new(&comp_input[..]);
 
-    deflater.read_to_end(&mut comp_output).unwrap();
+    deflater
+        .read_to_end(&mut comp_output)
+        .map_err(ParseError::InvalidDeflate)?;
+
+    Ok(comp_output)
+}
+
+// Bytes of the section whose tag is at `offset`
+fn section(x: &[u8], tag: u8, offset: usize, len: usize) -> Result<&[u8], ParseError> {
+    let start = offset + 1;
+    let available = x.len().saturating_sub(start);
 
-    parse_toggle(&comp_output)
+    if available < len {
+        return Err(ParseError::Truncated {
+            tag,
+            offset,
+            needed: len - available,
+        });
+    }
+
+    Ok(&x[start..(start + len)])
 }
 
-pub fn parse_toggle(x: &Vec<u8>) -> ToggleLevel {
+fn count_buttons(width: u8, height: u8, offset: usize) -> Result<usize, ParseError> {
+    if width == 0 || height == 0 {
+        return Err(ParseError::MissingDimensions { offset });
+    }
+
+    Ok(width as usize * height as usize)
+}
+
+/// Parses the inflated bytes of a level code. Sections carry no length, so
+/// everything from the first unknown tag to the end, known sections included,
+/// is kept as one RawSection.
+pub fn parse_toggle(x: &[u8]) -> Result<ToggleLevel, ParseError> {
+    read_toggle(x, false)
+}
+
+/// Parses the inflated bytes of a level code, an unknown tag is a ParseError::UnknownTag
+pub fn parse_toggle_strict(x: &[u8]) -> Result<ToggleLevel, ParseError> {
+    read_toggle(x, true)
+}
+
+fn read_toggle(x: &[u8], is_strict: bool) -> Result<ToggleLevel, ParseError> {
     let mut result = ToggleLevel::new();
 
-    const WIDTH_DEF: u8 = 0x01;
-    const HEIGHT_DEF: u8 = 0x02;
-    const SUBTYPES_DEF: u8 = 0x03;
-    const STATES_DEF: u8 = 0x04;
-    const MINIMUM_CLICK_DEF: u8 = 0x05;
-    const CREATOR_DEF: u8 = 0x06;
+    if x.is_empty() {
+        return Err(ParseError::Truncated {
+            tag: 0,
+            offset: 0,
+            needed: 1,
+        });
+    }
 
     let mut i = 0;
+    result.header = x[i];
 
-    let mut width = 1;
-    let mut height = 1;
-    let mut total_buttons = 1;
+    let mut width = 0;
+    let mut height = 0;
+    let mut subtypes_offset = None;
+    let mut states_offset = None;
     i += 1;
 
     let mut should_escape = false;
 
     while i < x.len() {
-        match x[i] {
+        let tag = x[i];
+        match tag {
             WIDTH_DEF => {
+                width = section(x, tag, i, 1)?[0];
                 i += 1;
-                width = x[i];
             }
             HEIGHT_DEF => {
+                height = section(x, tag, i, 1)?[0];
                 i += 1;
-                height = x[i];
             }
             SUBTYPES_DEF => {
-                total_buttons = width * height;
-
-                let start = i + 1;
-                let end = start + (total_buttons as usize);
-                let subtypes_range = &x[start..end];
+                let total_buttons = count_buttons(width, height, i)?;
+                let subtypes_range = section(x, tag, i, total_buttons)?;
 
-                i += total_buttons as usize;
+                result.subtypes = subtypes_range.to_vec();
+                subtypes_offset = Some(i);
 
-                result.subtypes.extend_from_slice(subtypes_range);
+                i += total_buttons;
             }
             STATES_DEF => {
-                let num_bytes: u32 = u32::from((total_buttons - 1) / 8 + 1);
-
-                let start = i + 1;
-                let end = start + (num_bytes as usize);
-                let states_range = &x[start..end];
+                let total_buttons = count_buttons(width, height, i)?;
+                let num_bytes = (total_buttons - 1) / 8 + 1;
+                let states_range = section(x, tag, i, num_bytes)?;
 
                 result.states = BitArray::from(states_range);
+                states_offset = Some(i);
 
-                i += num_bytes as usize;
+                i += num_bytes;
             }
             MINIMUM_CLICK_DEF => {
-                let start = i + 1;
-                let end = start + 4;
-
-                let integer_bytes: [u8; 4] = (&x[start..end]).try_into().unwrap();
+                let integer_bytes: [u8; 4] = section(x, tag, i, 4)?.try_into().unwrap();
 
                 let a = i32::from_le_bytes(integer_bytes);
-                result.min_clicks = u32::try_from(a).unwrap();
+                result.min_clicks =
+                    u32::try_from(a).map_err(|_| ParseError::NegativeMinClicks {
+                        offset: i,
+                        value: a,
+                    })?;
                 i += 4;
             }
 
             CREATOR_DEF => {
-                should_escape = true;
+                let (creator, length) = read_string(x, i)?;
+                result.creator = creator;
+                i += length;
+            }
+
+            // Either goal section alone leaves the other part of the default goal
+            GOAL_DEF | CARE_DEF => {
+                let total_buttons = count_buttons(width, height, i)?;
+                let num_bytes = (total_buttons - 1) / 8 + 1;
+                let bits = BitArray::from(section(x, tag, i, num_bytes)?);
+
+                let goal = result.goal.get_or_insert_with(|| Goal::new(total_buttons));
+                if tag == GOAL_DEF {
+                    goal.target = bits;
+                } else {
+                    goal.care = bits;
+                }
+
+                i += num_bytes;
             }
 
-            _ => {}
+            TOPOLOGY_DEF => {
+                let value = section(x, tag, i, 1)?[0];
+                result.topology = match value {
+                    0 => Topology::Bounded,
+                    1 => Topology::Torus,
+                    2 => Topology::Cylinder,
+                    _ => return Err(ParseError::UnknownTopology { offset: i, value }),
+                };
+                i += 1;
+            }
+
+            // Ray mode byte followed by one bit per cell, set for voids
+            VOID_DEF => {
+                let total_buttons = count_buttons(width, height, i)?;
+                let num_bytes = (total_buttons - 1) / 8 + 1;
+                let data = section(x, tag, i, 1 + num_bytes)?;
+
+                result.ray_mode = match data[0] {
+                    0 => RayMode::Block,
+                    1 => RayMode::Skip,
+                    value => return Err(ParseError::UnknownRayMode { offset: i, value }),
+                };
+                result.voids = Some(BitArray::from(&data[1..]));
+
+                i += 1 + num_bytes;
+            }
+
+            // Sections carry no length, so everything from an unknown tag
+            // onwards is kept as is
+            _ if is_strict => return Err(ParseError::UnknownTag { offset: i, tag }),
+            _ => {
+                result.unknown_sections.push(RawSection {
+                    tag,
+                    data: x[(i + 1)..].to_vec(),
+                });
+
+                should_escape = true;
+            }
         }
         i += 1;
 
@@ -91,8 +313,246 @@ pub fn parse_toggle(x: &Vec<u8>) -> ToggleLevel {
         }
     }
 
+    let total_buttons = count_buttons(width, height, x.len())?;
+
+    let subtypes_found = result.subtypes.len();
+    if subtypes_found != total_buttons {
+        return Err(ParseError::LengthMismatch {
+            tag: SUBTYPES_DEF,
+            offset: subtypes_offset.unwrap_or(x.len()),
+            expected: total_buttons,
+            found: subtypes_found,
+        });
+    }
+
+    let states_found = result.states.len();
+    if states_found < total_buttons {
+        return Err(ParseError::LengthMismatch {
+            tag: STATES_DEF,
+            offset: states_offset.unwrap_or(x.len()),
+            expected: total_buttons,
+            found: states_found,
+        });
+    }
+
     result.width = u32::from(width);
     result.height = u32::from(height);
 
-    result
+    Ok(result)
+}
+
+// Strings are stored as a 7-bit variable-length byte count followed by UTF-8.
+// Returns the string and the number of bytes read after the tag at `offset`.
+fn read_string(x: &[u8], offset: usize) -> Result<(String, usize), ParseError> {
+    let mut length: usize = 0;
+    let mut shift = 0;
+    let mut i = 0;
+
+    loop {
+        let byte = section(x, CREATOR_DEF, offset, i + 1)?[i];
+        i += 1;
+
+        length |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if i == MAX_LENGTH_BYTES {
+            return Err(ParseError::InvalidLength { offset });
+        }
+    }
+
+    let total = i
+        .checked_add(length)
+        .ok_or(ParseError::InvalidLength { offset })?;
+    let bytes = &section(x, CREATOR_DEF, offset, total)?[i..];
+    let text = String::from_utf8_lossy(bytes).into_owned();
+    Ok((text, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 2x1 board with no buttons, cut off right after the states tag
+    const SMALL_LEVEL: [u8; 9] = [
+        0,
+        WIDTH_DEF,
+        2,
+        HEIGHT_DEF,
+        1,
+        SUBTYPES_DEF,
+        0,
+        0,
+        STATES_DEF,
+    ];
+
+    // Every light off, followed by `section`
+    fn small_level_with(section: &[u8]) -> Vec<u8> {
+        let mut result = SMALL_LEVEL.to_vec();
+        result.push(0);
+        result.extend_from_slice(section);
+        result
+    }
+
+    #[test]
+    fn small_level_parses() {
+        let level = parse_toggle(&small_level_with(&[])).unwrap();
+        assert_eq!(level.width, 2);
+        assert_eq!(level.height, 1);
+        assert_eq!(level.subtypes, vec![0, 0]);
+    }
+
+    #[test]
+    fn empty_input_is_truncated() {
+        assert!(matches!(
+            parse_toggle(&[]),
+            Err(ParseError::Truncated { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_codes_are_rejected() {
+        assert!(matches!(
+            parse("not base64!".to_string()),
+            Err(ParseError::InvalidBase64(_))
+        ));
+        assert!(matches!(
+            parse(base64::encode([0xff; 8])),
+            Err(ParseError::InvalidDeflate(_))
+        ));
+    }
+
+    #[test]
+    fn cut_off_sections_are_truncated() {
+        // States missing entirely
+        assert!(matches!(
+            parse_toggle(&SMALL_LEVEL),
+            Err(ParseError::Truncated {
+                tag: STATES_DEF,
+                offset: 8,
+                needed: 1,
+            })
+        ));
+        // One subtype short
+        assert!(matches!(
+            parse_toggle(&SMALL_LEVEL[..7]),
+            Err(ParseError::Truncated {
+                tag: SUBTYPES_DEF,
+                offset: 5,
+                needed: 1,
+            })
+        ));
+        assert!(matches!(
+            parse_toggle(&small_level_with(&[MINIMUM_CLICK_DEF, 1, 0])),
+            Err(ParseError::Truncated {
+                tag: MINIMUM_CLICK_DEF,
+                needed: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn sections_need_dimensions() {
+        assert!(matches!(
+            parse_toggle(&[0, WIDTH_DEF, 2, SUBTYPES_DEF, 0, 0]),
+            Err(ParseError::MissingDimensions { offset: 3 })
+        ));
+        assert!(matches!(
+            parse_toggle(&[0]),
+            Err(ParseError::MissingDimensions { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_subtypes_are_a_mismatch() {
+        assert!(matches!(
+            parse_toggle(&[0, WIDTH_DEF, 2, HEIGHT_DEF, 1, STATES_DEF, 0]),
+            Err(ParseError::LengthMismatch {
+                tag: SUBTYPES_DEF,
+                expected: 2,
+                found: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert!(matches!(
+            parse_toggle(&small_level_with(&[
+                MINIMUM_CLICK_DEF,
+                0xff,
+                0xff,
+                0xff,
+                0xff
+            ])),
+            Err(ParseError::NegativeMinClicks { value: -1, .. })
+        ));
+        assert!(matches!(
+            parse_toggle(&small_level_with(&[TOPOLOGY_DEF, 3])),
+            Err(ParseError::UnknownTopology { value: 3, .. })
+        ));
+        assert!(matches!(
+            parse_toggle(&small_level_with(&[VOID_DEF, 2, 0])),
+            Err(ParseError::UnknownRayMode { value: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_tags_are_kept_unless_strict() {
+        let code = small_level_with(&[0x7f, 1, 2, TOPOLOGY_DEF, 1]);
+
+        let level = parse_toggle(&code).unwrap();
+        let section = RawSection {
+            tag: 0x7f,
+            data: vec![1, 2, TOPOLOGY_DEF, 1],
+        };
+        assert_eq!(level.unknown_sections, vec![section]);
+        assert_eq!(level.topology, Topology::Bounded);
+
+        assert!(matches!(
+            parse_toggle_strict(&code),
+            Err(ParseError::UnknownTag {
+                offset: 10,
+                tag: 0x7f,
+            })
+        ));
+    }
+
+    #[test]
+    fn string_lengths_are_checked() {
+        let level = parse_toggle(&small_level_with(&[CREATOR_DEF, 2, b'h', b'i'])).unwrap();
+        assert_eq!(level.creator, "hi");
+
+        assert!(matches!(
+            parse_toggle(&small_level_with(&[CREATOR_DEF, 5, b'h', b'i'])),
+            Err(ParseError::Truncated {
+                tag: CREATOR_DEF,
+                needed: 3,
+                ..
+            })
+        ));
+
+        // Five length bytes are read, but the string is nowhere near that long
+        let huge = [CREATOR_DEF, 0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert!(matches!(
+            parse_toggle(&small_level_with(&huge)),
+            Err(ParseError::Truncated {
+                tag: CREATOR_DEF,
+                ..
+            })
+        ));
+
+        // A length that would reach usize::MAX
+        let mut endless = vec![CREATOR_DEF];
+        endless.extend_from_slice(&[0xff; 9]);
+        endless.push(0x01);
+        assert!(matches!(
+            parse_toggle(&small_level_with(&endless)),
+            Err(ParseError::InvalidLength { offset: 10 })
+        ));
+    }
 }
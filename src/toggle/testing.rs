@@ -0,0 +1,93 @@
+//! Helpers shared by the unit tests
+
+use std::collections::{HashMap, VecDeque};
+
+use super::bitgrid::BitGrid;
+use super::core::{Goal, Grid, ToggleLevel};
+use super::utils::{BitArray, Rng, StateKey};
+
+/// Subtypes of the built-in buttons that only toggle lights, Nothing included
+pub const LINEAR_SUBTYPES: [u8; 17] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 20, 21, 24];
+
+/// Level with buttons drawn from `subtypes` and random lights
+pub fn random_level(width: u32, height: u32, subtypes: &[u8], rng: &mut Rng) -> ToggleLevel {
+    let mut level = ToggleLevel::new();
+    level.width = width;
+    level.height = height;
+    level.subtypes = (0..level.num_cells())
+        .map(|_| *rng.choose(subtypes))
+        .collect();
+    level.states = BitArray::new(level.num_cells());
+    for cell in 0..level.num_cells() {
+        level.states.set(cell, rng.chance(0.5));
+    }
+    level
+}
+
+/// Level that starts all off and is scrambled by `clicks` random clicks, so it can be solved
+pub fn scrambled_level(
+    width: u32,
+    height: u32,
+    subtypes: &[u8],
+    clicks: usize,
+    rng: &mut Rng,
+) -> ToggleLevel {
+    let mut level = random_level(width, height, subtypes, rng);
+    level.states = BitArray::new(level.num_cells());
+    let mut grid = Grid::from_level(&level);
+    for _ in 0..clicks {
+        grid.click(
+            rng.below(width as usize) as i32,
+            rng.below(height as usize) as i32,
+        );
+    }
+    level.states = grid.get_states();
+    level
+}
+
+/// Gives the level a random goal that leaves some cells free
+pub fn random_goal(level: &mut ToggleLevel, rng: &mut Rng) {
+    let mut goal = Goal::new(level.num_cells());
+    for cell in 0..level.num_cells() {
+        goal.target.set(cell, rng.chance(0.5));
+        goal.care.set(cell, rng.chance(0.7));
+    }
+    level.goal = Some(goal);
+}
+
+/// Fewest clicks from every state reachable from the start of the level,
+/// found by breadth-first search. Only for boards of a few cells.
+pub fn distances(level: &ToggleLevel) -> HashMap<StateKey, usize> {
+    let mut grid = BitGrid::from_level(level);
+    let mut result = HashMap::from([(grid.to_key(), 0)]);
+    let mut queue = VecDeque::from([grid.to_key()]);
+
+    while let Some(key) = queue.pop_front() {
+        let distance = result[&key];
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                grid.set_key(&key);
+                grid.click(x, y);
+                result.entry(grid.to_key()).or_insert_with(|| {
+                    queue.push_back(grid.to_key());
+                    distance + 1
+                });
+            }
+        }
+    }
+
+    result
+}
+
+/// Fewest clicks that reach the goal, None if no reachable state does
+pub fn shortest_solution(level: &ToggleLevel) -> Option<usize> {
+    let mut grid = BitGrid::from_level(level);
+    distances(level)
+        .into_iter()
+        .filter(|(key, _)| {
+            grid.set_key(key);
+            grid.is_reached()
+        })
+        .map(|(_, distance)| distance)
+        .min()
+}
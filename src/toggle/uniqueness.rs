@@ -0,0 +1,426 @@
+use super::astar::{self, SolverConfig};
+use super::bitgrid::BitGrid;
+use super::core::{ButtonFunction, Coordinate, Grid, ToggleLevel};
+use super::heuristic::{Admissible, Heuristic};
+use super::linear;
+use super::utils::BitArray;
+
+// Search nodes the counter may visit before giving up
+const COUNT_BUDGET: usize = 1_000_000;
+
+/// Minimum-click solutions of a level. Orders of the same clicks that only
+/// swap commuting buttons are one solution.
+#[derive(Debug, Clone)]
+pub struct SolutionCount {
+    /// First solution found, in the order the counter enumerates them
+    pub solution: Vec<Coordinate>,
+    pub count: u64,
+    /// False if counting stopped early, then `count` is only a lower bound
+    pub complete: bool,
+}
+
+impl SolutionCount {
+    pub fn is_unique(&self) -> bool {
+        self.complete && self.count == 1
+    }
+}
+
+//
+// Solution Counter
+//
+// Depth-first search over click sequences of exactly the optimal length,
+// pruned with the admissible heuristic. Only the lexicographically smallest
+// order of every set of commuting clicks is visited: a button may not follow
+// a larger button it commutes with, unless a button it does not commute with
+// lies in between.
+//
+struct SolutionCounter {
+    grid: BitGrid,
+    buttons: Vec<Coordinate>,
+    commutes: Vec<Vec<bool>>,
+    heuristic: Admissible,
+    path: Vec<usize>,
+    first: Option<Vec<usize>>,
+    count: u64,
+    max_count: u64,
+    budget: usize,
+}
+
+impl SolutionCounter {
+    fn new(level: &ToggleLevel, max_count: u64) -> SolutionCounter {
+        let grid = Grid::from_level(level);
+
+        let mut buttons = Vec::new();
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                if !matches!(grid.at(x, y).unwrap().func, ButtonFunction::Nothing) {
+                    buttons.push(Coordinate::new(x, y));
+                }
+            }
+        }
+
+        let goal = level.goal();
+        let mut bit_grid = BitGrid::from_grid(&grid);
+        bit_grid.set_goal(&goal);
+        SolutionCounter {
+            commutes: commute_table(&grid, &buttons),
+            grid: bit_grid,
+            buttons,
+            heuristic: Admissible::with_goal(&grid, &goal),
+            path: Vec::new(),
+            first: None,
+            count: 0,
+            max_count,
+            budget: COUNT_BUDGET,
+        }
+    }
+
+    fn is_normal_form(&self, button: usize) -> bool {
+        for &previous in self.path.iter().rev() {
+            if !self.commutes[button][previous] {
+                return true;
+            }
+            if previous > button {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Returns false once counting has to stop
+    fn search(&mut self, depth: usize) -> bool {
+        let diff = self.grid.remaining() as i32;
+        if depth == 0 {
+            if diff == 0 {
+                self.count += 1;
+                if self.first.is_none() {
+                    self.first = Some(self.path.clone());
+                }
+            }
+            return self.count < self.max_count;
+        }
+        if self.heuristic.estimate(&self.grid) > depth as i32 {
+            return true;
+        }
+
+        if self.budget == 0 {
+            return false;
+        }
+        self.budget -= 1;
+
+        let saved = self.grid.words().to_vec();
+        for button in 0..self.buttons.len() {
+            if !self.is_normal_form(button) {
+                continue;
+            }
+
+            let coord = self.buttons[button];
+            self.grid.click(coord.x, coord.y);
+            self.path.push(button);
+
+            let proceed = self.search(depth - 1);
+
+            self.path.pop();
+            self.grid.set_words(&saved);
+            if !proceed {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// Two clicks commute when both are XORs or they touch disjoint cells.
+// Otherwise both orders are compared on the zero state and on every
+// single-cell state, which decides it since every click is affine.
+fn commute_table(grid: &Grid, buttons: &[Coordinate]) -> Vec<Vec<bool>> {
+    let num_cells = (grid.width() * grid.height()) as usize;
+    let height = grid.height();
+    let index_of = |c: &Coordinate| (c.x * height + c.y) as usize;
+
+    let affected: Vec<Vec<bool>> = buttons
+        .iter()
+        .map(|coord| {
+            let mut cells = vec![false; num_cells];
+            for cell in grid.affected_cells(coord.x, coord.y) {
+                cells[index_of(&cell)] = true;
+            }
+            cells
+        })
+        .collect();
+    let is_linear: Vec<bool> = buttons
+        .iter()
+        .map(|coord| grid.at(coord.x, coord.y).unwrap().func.is_linear())
+        .collect();
+
+    let mut probe = BitGrid::from_grid(grid);
+    let mut probe_states: Vec<BitArray> = vec![BitArray::new(num_cells)];
+    for i in 0..num_cells {
+        let mut states = BitArray::new(num_cells);
+        states.set(i, true);
+        probe_states.push(states);
+    }
+
+    let mut result = vec![vec![true; buttons.len()]; buttons.len()];
+    for a in 0..buttons.len() {
+        for b in (a + 1)..buttons.len() {
+            let overlaps = (0..num_cells).any(|i| affected[a][i] && affected[b][i]);
+            if !overlaps || (is_linear[a] && is_linear[b]) {
+                continue;
+            }
+
+            let (first, second) = (buttons[a], buttons[b]);
+            let commutes = probe_states.iter().all(|states| {
+                probe.set_states(states);
+                probe.click(first.x, first.y);
+                probe.click(second.x, second.y);
+                let forward = probe.words().to_vec();
+
+                probe.set_states(states);
+                probe.click(second.x, second.y);
+                probe.click(first.x, first.y);
+                forward == probe.words()
+            });
+
+            result[a][b] = commutes;
+            result[b][a] = commutes;
+        }
+    }
+
+    result
+}
+
+/// Counts the solutions that take exactly `clicks` clicks, stopping at `max_count`.
+/// `clicks` should be the optimal number of clicks, longer solutions that only
+/// add clicks which cancel out are counted as well.
+pub fn count_with_clicks(level: &ToggleLevel, clicks: usize, max_count: u64) -> SolutionCount {
+    let mut counter = SolutionCounter::new(level, max_count);
+    let complete = counter.search(clicks);
+
+    let buttons = &counter.buttons;
+    let solution = counter
+        .first
+        .as_ref()
+        .map(|path| path.iter().map(|&button| buttons[button]).collect())
+        .unwrap_or_default();
+
+    SolutionCount {
+        solution,
+        count: counter.count,
+        complete,
+    }
+}
+
+/// Counts the minimum-click solutions, stopping at `max_count`. Returns None if the
+/// level cannot be solved or its minimum is not found within astar::DEFAULT_MAX_NODES.
+pub fn count_solutions(level: &ToggleLevel, max_count: u64) -> Option<SolutionCount> {
+    let clicks = match linear::solve_linear(level) {
+        linear::Result::Success(orders) => orders.len(),
+        // Counting against an approximate solution would also count longer solutions
+        linear::Result::Fail | linear::Result::Approximate(_) => return None,
+        linear::Result::NotLinear => {
+            let mut config = SolverConfig::optimal(level);
+            config.limits.max_nodes = Some(astar::DEFAULT_MAX_NODES);
+            match astar::run_astar_with(level, &config).result {
+                astar::Result::Success(orders) => orders.len(),
+                _ => return None,
+            }
+        }
+    };
+
+    Some(count_with_clicks(level, clicks, max_count))
+}
+
+/// True if the level has exactly one minimum-click solution
+pub fn is_unique(level: &ToggleLevel) -> bool {
+    count_solutions(level, 2).is_some_and(|count| count.is_unique())
+}
+
+pub struct Minimized {
+    pub level: ToggleLevel,
+    /// The only minimum-click solution of both levels
+    pub solution: Vec<Coordinate>,
+    /// Buttons replaced with Nothing
+    pub removed: Vec<Coordinate>,
+}
+
+/// Replaces every button the unique solution does not click with Nothing.
+/// Removing a button can only remove solutions, so the solution stays unique.
+/// Returns None if the level cannot be solved, is too large to solve exactly
+/// or has more than one minimum-click solution.
+pub fn minimize(level: &ToggleLevel) -> Option<Minimized> {
+    let count = count_solutions(level, 2)?;
+    if !count.is_unique() {
+        return None;
+    }
+
+    let mut result = level.clone();
+    let mut removed = Vec::new();
+    for y in 0..level.height {
+        for x in 0..level.width {
+            let coord = Coordinate::new(x as i32, y as i32);
+            if level.subtype(x, y) == 0 || count.solution.contains(&coord) {
+                continue;
+            }
+
+            result.set_subtype(x, y, 0);
+            removed.push(coord);
+        }
+    }
+
+    Some(Minimized {
+        level: result,
+        solution: count.solution,
+        removed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::toggle::testing::{random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::Rng;
+
+    // Both orders of every pair of buttons compared on every state of the board
+    fn brute_force_commutes(level: &ToggleLevel, buttons: &[Coordinate]) -> Vec<Vec<bool>> {
+        let num_cells = level.num_cells();
+        let mut grid = BitGrid::from_level(level);
+        let mut result = vec![vec![true; buttons.len()]; buttons.len()];
+        for (a, first) in buttons.iter().enumerate() {
+            for (b, second) in buttons.iter().enumerate() {
+                result[a][b] = (0..1usize << num_cells).all(|bits| {
+                    let mut states = BitArray::new(num_cells);
+                    for cell in 0..num_cells {
+                        states.set(cell, bits >> cell & 1 == 1);
+                    }
+
+                    grid.set_states(&states);
+                    grid.click(first.x, first.y);
+                    grid.click(second.x, second.y);
+                    let forward = grid.get_states();
+
+                    grid.set_states(&states);
+                    grid.click(second.x, second.y);
+                    grid.click(first.x, first.y);
+                    forward == grid.get_states()
+                });
+            }
+        }
+        result
+    }
+
+    // Smallest sequence reachable by swapping adjacent commuting clicks
+    fn canonical(sequence: &[usize], commutes: &[Vec<bool>]) -> Vec<usize> {
+        let mut seen = HashSet::from([sequence.to_vec()]);
+        let mut stack = vec![sequence.to_vec()];
+        while let Some(current) = stack.pop() {
+            for i in 1..current.len() {
+                if commutes[current[i - 1]][current[i]] {
+                    let mut next = current.clone();
+                    next.swap(i - 1, i);
+                    if seen.insert(next.clone()) {
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        seen.into_iter().min().unwrap()
+    }
+
+    // Classes of click sequences of length `clicks` that reach the goal
+    fn brute_force_count(level: &ToggleLevel, clicks: usize) -> usize {
+        let mut buttons = Vec::new();
+        for y in 0..level.height {
+            for x in 0..level.width {
+                if level.subtype(x, y) != 0 {
+                    buttons.push(Coordinate::new(x as i32, y as i32));
+                }
+            }
+        }
+        let commutes = brute_force_commutes(level, &buttons);
+
+        let mut grid = BitGrid::from_level(level);
+        let start = grid.get_states();
+        let mut classes = HashSet::new();
+        for index in 0..buttons.len().pow(clicks as u32) {
+            let sequence: Vec<usize> = (0..clicks)
+                .map(|i| index / buttons.len().pow(i as u32) % buttons.len())
+                .collect();
+
+            grid.set_states(&start);
+            for &button in &sequence {
+                grid.click(buttons[button].x, buttons[button].y);
+            }
+            if grid.is_reached() {
+                classes.insert(canonical(&sequence, &commutes));
+            }
+        }
+        classes.len()
+    }
+
+    #[test]
+    fn count_matches_brute_force() {
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut rng = Rng::new(53);
+        for (width, height) in [(2, 2), (3, 2)] {
+            for i in 0..40 {
+                let mut level = random_level(width, height, &subtypes, &mut rng);
+                if i % 2 == 1 {
+                    random_goal(&mut level, &mut rng);
+                }
+
+                let clicks = shortest_solution(&level);
+                let count = count_solutions(&level, u64::MAX);
+                match (clicks, count) {
+                    (Some(clicks), Some(count)) => {
+                        assert!(count.complete);
+                        assert_eq!(count.solution.len(), clicks);
+                        if clicks <= 5 {
+                            assert_eq!(count.count as usize, brute_force_count(&level, clicks));
+                        }
+                    }
+                    (None, None) => {}
+                    (clicks, count) => panic!("{:?} clicks but counted {:?}", clicks, count),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn minimize_keeps_the_solution_unique() {
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut rng = Rng::new(59);
+        let (mut unique, mut ambiguous) = (0, 0);
+        for _ in 0..100 {
+            let level = random_level(3, 2, &subtypes, &mut rng);
+            let Some(count) = count_solutions(&level, 2) else {
+                assert!(minimize(&level).is_none());
+                continue;
+            };
+
+            match minimize(&level) {
+                Some(minimized) => {
+                    assert!(count.is_unique());
+                    unique += 1;
+
+                    let after = count_solutions(&minimized.level, 2).unwrap();
+                    assert!(after.is_unique());
+                    assert_eq!(after.solution, minimized.solution);
+                    assert_eq!(minimized.solution, count.solution);
+                    for coord in &minimized.removed {
+                        assert_eq!(minimized.level.subtype(coord.x as u32, coord.y as u32), 0);
+                        assert!(!minimized.solution.contains(coord));
+                    }
+                }
+                None => {
+                    assert!(!count.is_unique());
+                    ambiguous += 1;
+                }
+            }
+        }
+        assert!(unique > 0 && ambiguous > 0);
+    }
+}
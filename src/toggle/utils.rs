@@ -1,3 +1,10 @@
+/// Compact hashable copy of a BitArray's bits
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub(crate) enum StateKey {
+    Small(u128),
+    Large(Box<[u64]>),
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct BitArray {
     bytes: Vec<u8>,
@@ -23,6 +30,10 @@ impl BitArray {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn from(r: &[u8]) -> BitArray {
         BitArray { bytes: Vec::from(r), len: r.len() * 8}
     }
@@ -43,7 +54,7 @@ impl BitArray {
         let byte_index = pos / 8;
         let bit_pos = pos % 8;
 
-        return (self.bytes[byte_index] & (1 << bit_pos)) != 0; 
+        (self.bytes[byte_index] & (1 << bit_pos)) != 0
     }
 
     pub fn set(&mut self, pos: usize, is_on: bool) {
@@ -73,10 +84,81 @@ impl BitArray {
             }
             print!(" ");
         }
-        println!("");
+        println!();
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Flips every bit that is set in `other`
+    pub fn xor(&mut self, other: &BitArray) {
+        for (a, b) in self.bytes.iter_mut().zip(other.bytes.iter()) {
+            *a ^= *b;
+        }
+    }
+
+    pub fn count_ones(&self) -> u32 {
+        self.bytes.iter().map(|b| b.count_ones()).sum()
+    }
+
+    pub(crate) fn from_key(key: &StateKey, len: usize) -> BitArray {
+        let mut result = BitArray::new(len);
+        let num_bytes = result.bytes.len();
+
+        match key {
+            StateKey::Small(word) => {
+                result
+                    .bytes
+                    .copy_from_slice(&word.to_le_bytes()[..num_bytes]);
+            }
+            StateKey::Large(words) => {
+                let bytes = words.iter().flat_map(|word| word.to_le_bytes());
+                for (dst, src) in result.bytes.iter_mut().zip(bytes) {
+                    *dst = src;
+                }
+            }
+        }
+
+        result
     }
 
     pub fn to_base64(&self) -> String {
         base64::encode(&self.bytes)
     }
-}
\ No newline at end of file
+}
+
+/// Seeded SplitMix64 generator, the same seed gives the same numbers on every platform
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform number in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        ((u128::from(self.next_u64()) * n as u128) >> 64) as usize
+    }
+
+    /// True with probability `p`
+    pub fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
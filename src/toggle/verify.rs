@@ -0,0 +1,179 @@
+use std::fmt;
+
+use super::core::{ButtonFunction, Coordinate, Grid, ToggleLevel};
+use super::utils::BitArray;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidReason {
+    OutOfRange,
+    Nothing,
+}
+
+/// First click of a replay that could not be applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidClick {
+    pub index: usize,
+    pub coord: Coordinate,
+    pub reason: InvalidReason,
+}
+
+impl fmt::Display for InvalidClick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            InvalidReason::OutOfRange => "is out of range",
+            InvalidReason::Nothing => "has no button",
+        };
+        write!(
+            f,
+            "Click {} at {},{} {}",
+            self.index + 1,
+            self.coord.x,
+            self.coord.y,
+            reason
+        )
+    }
+}
+
+pub struct Replay {
+    /// True if the final states match the goal of the level
+    pub cleared: bool,
+    /// The initial states followed by the states after every applied click
+    pub states: Vec<BitArray>,
+    pub invalid: Option<InvalidClick>,
+}
+
+impl Replay {
+    pub fn is_valid(&self) -> bool {
+        self.invalid.is_none()
+    }
+
+    pub fn final_states(&self) -> &BitArray {
+        self.states.last().unwrap()
+    }
+}
+
+/// Clicks `orders` on the level in sequence and reports whether they reach the level's goal.
+/// The replay stops at the first click that is out of range or on a Nothing button.
+pub fn replay(level: &ToggleLevel, orders: &[Coordinate]) -> Replay {
+    let mut grid = Grid::from_level(level);
+    let mut states = Vec::with_capacity(orders.len() + 1);
+    states.push(grid.get_states());
+
+    for (index, coord) in orders.iter().enumerate() {
+        let reason = if !grid.check_range(coord.x, coord.y) {
+            Some(InvalidReason::OutOfRange)
+        } else if let ButtonFunction::Nothing = grid.at(coord.x, coord.y).unwrap().func {
+            Some(InvalidReason::Nothing)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            return Replay {
+                cleared: false,
+                states,
+                invalid: Some(InvalidClick {
+                    index,
+                    coord: *coord,
+                    reason,
+                }),
+            };
+        }
+
+        grid.click(coord.x, coord.y);
+        states.push(grid.get_states());
+    }
+
+    Replay {
+        cleared: level.goal().is_reached(states.last().unwrap()),
+        states,
+        invalid: None,
+    }
+}
+
+/// Shorthand for `replay(level, orders).cleared`
+pub fn is_solution(level: &ToggleLevel, orders: &[Coordinate]) -> bool {
+    replay(level, orders).cleared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::astar;
+    use crate::toggle::bitgrid::BitGrid;
+    use crate::toggle::testing::{random_goal, random_level, shortest_solution};
+    use crate::toggle::utils::Rng;
+
+    #[test]
+    fn replay_matches_bit_grid_clicks() {
+        let subtypes: Vec<u8> = (1..26).collect();
+        let mut rng = Rng::new(31);
+        for i in 0..50 {
+            let mut level = random_level(3, 3, &subtypes, &mut rng);
+            if i % 2 == 1 {
+                random_goal(&mut level, &mut rng);
+            }
+            let orders: Vec<Coordinate> = (0..rng.below(6))
+                .map(|_| Coordinate::new(rng.below(3) as i32, rng.below(3) as i32))
+                .collect();
+
+            let result = replay(&level, &orders);
+            assert!(result.is_valid());
+            assert_eq!(result.states.len(), orders.len() + 1);
+
+            let mut grid = BitGrid::from_level(&level);
+            assert_eq!(result.states[0], grid.get_states());
+            for (order, states) in orders.iter().zip(result.states[1..].iter()) {
+                grid.click(order.x, order.y);
+                assert_eq!(*states, grid.get_states());
+            }
+            assert_eq!(result.cleared, grid.is_reached());
+        }
+    }
+
+    #[test]
+    fn shortest_solutions_are_cleared() {
+        let subtypes: Vec<u8> = (0..26).collect();
+        let mut rng = Rng::new(37);
+        for _ in 0..50 {
+            let level = random_level(3, 2, &subtypes, &mut rng);
+            let clicks = shortest_solution(&level);
+            match astar::run_optimal(&level) {
+                astar::Result::Success(orders) => {
+                    assert_eq!(Some(orders.len()), clicks);
+                    assert!(is_solution(&level, &orders));
+                    if !orders.is_empty() {
+                        assert!(!is_solution(&level, &orders[1..]));
+                    }
+                }
+                _ => assert_eq!(clicks, None),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_clicks_stop_the_replay() {
+        let mut level = ToggleLevel::new();
+        level.width = 2;
+        level.height = 1;
+        level.subtypes = vec![0, 11];
+        level.states = BitArray::new(2);
+
+        let orders = [
+            Coordinate::new(1, 0),
+            Coordinate::new(2, 0),
+            Coordinate::new(1, 0),
+        ];
+        let result = replay(&level, &orders);
+        assert!(!result.cleared);
+        assert_eq!(result.states.len(), 2);
+        let invalid = result.invalid.unwrap();
+        assert_eq!(
+            (invalid.index, invalid.reason),
+            (1, InvalidReason::OutOfRange)
+        );
+
+        let result = replay(&level, &[Coordinate::new(0, 0)]);
+        assert_eq!(result.invalid.unwrap().reason, InvalidReason::Nothing);
+    }
+}
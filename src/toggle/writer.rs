@@ -0,0 +1,259 @@
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+use crate::toggle::core::{RayMode, ToggleLevel, Topology};
+use crate::toggle::reader::{
+    CARE_DEF, CREATOR_DEF, GOAL_DEF, HEIGHT_DEF, MINIMUM_CLICK_DEF, STATES_DEF, SUBTYPES_DEF,
+    TOPOLOGY_DEF, VOID_DEF, WIDTH_DEF,
+};
+use crate::toggle::utils::BitArray;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    // Width and height are stored in one byte each
+    WidthTooLarge { width: u32 },
+    HeightTooLarge { height: u32 },
+    // min_clicks is stored as an i32
+    MinClicksTooLarge { min_clicks: u32 },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::WidthTooLarge { width } => {
+                write!(f, "width {} does not fit in a byte", width)
+            }
+            EncodeError::HeightTooLarge { height } => {
+                write!(f, "height {} does not fit in a byte", height)
+            }
+            EncodeError::MinClicksTooLarge { min_clicks } => {
+                write!(f, "min_clicks {} does not fit in an i32", min_clicks)
+            }
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+/// Encodes a level into a base64 level code that `reader::parse` accepts
+pub fn encode(level: &ToggleLevel) -> Result<String, EncodeError> {
+    let comp_input = write_toggle(level)?;
+
+    let mut deflater = DeflateEncoder::new(Vec::new(), Compression::best());
+    deflater.write_all(&comp_input).unwrap();
+    let comp_output = deflater.finish().unwrap();
+
+    Ok(base64::encode(comp_output))
+}
+
+/// Serializes a level into the bytes that get deflated into a level code
+pub fn write_toggle(level: &ToggleLevel) -> Result<Vec<u8>, EncodeError> {
+    let mut result = vec![level.header];
+
+    let width =
+        u8::try_from(level.width).map_err(|_| EncodeError::WidthTooLarge { width: level.width })?;
+    let height = u8::try_from(level.height).map_err(|_| EncodeError::HeightTooLarge {
+        height: level.height,
+    })?;
+    let total_buttons = level.num_cells();
+
+    result.push(WIDTH_DEF);
+    result.push(width);
+
+    result.push(HEIGHT_DEF);
+    result.push(height);
+
+    result.push(SUBTYPES_DEF);
+    for i in 0..total_buttons {
+        result.push(level.subtypes.get(i).copied().unwrap_or(0));
+    }
+
+    result.push(STATES_DEF);
+    write_bits(&mut result, &level.states, total_buttons);
+
+    result.push(MINIMUM_CLICK_DEF);
+    let min_clicks =
+        i32::try_from(level.min_clicks).map_err(|_| EncodeError::MinClicksTooLarge {
+            min_clicks: level.min_clicks,
+        })?;
+    result.extend_from_slice(&min_clicks.to_le_bytes());
+
+    if !level.creator.is_empty() {
+        result.push(CREATOR_DEF);
+        write_string(&mut result, &level.creator);
+    }
+
+    // Levels that only need every light off keep the original format
+    let goal = level.goal.as_ref();
+    if let Some(goal) = goal.filter(|goal| !goal.is_all_off(total_buttons)) {
+        result.push(GOAL_DEF);
+        write_bits(&mut result, &goal.target, total_buttons);
+
+        result.push(CARE_DEF);
+        write_bits(&mut result, &goal.care, total_buttons);
+    }
+
+    // Bounded boards keep the original format as well
+    let topology = match level.topology {
+        Topology::Bounded => None,
+        Topology::Torus => Some(1),
+        Topology::Cylinder => Some(2),
+    };
+    if let Some(topology) = topology {
+        result.push(TOPOLOGY_DEF);
+        result.push(topology);
+    }
+
+    // So do boards without holes
+    let voids = level.voids.as_ref();
+    if let Some(voids) = voids.filter(|voids| (0..total_buttons).any(|i| voids.get(i))) {
+        result.push(VOID_DEF);
+        result.push(match level.ray_mode {
+            RayMode::Block => 0,
+            RayMode::Skip => 1,
+        });
+        write_bits(&mut result, voids, total_buttons);
+    }
+
+    for section in level.unknown_sections.iter() {
+        result.push(section.tag);
+        result.extend_from_slice(&section.data);
+    }
+
+    Ok(result)
+}
+
+fn write_bits(result: &mut Vec<u8>, bits: &BitArray, total_buttons: usize) {
+    let num_bytes = (total_buttons.max(1) - 1) / 8 + 1;
+    let bytes = bits.bytes();
+    for i in 0..num_bytes {
+        result.push(bytes.get(i).copied().unwrap_or(0));
+    }
+}
+
+// 7-bit variable-length byte count followed by UTF-8, as read_string expects
+fn write_string(result: &mut Vec<u8>, text: &str) {
+    let mut length = text.len();
+    while length >= 0x80 {
+        result.push((length as u8 & 0x7f) | 0x80);
+        length >>= 7;
+    }
+    result.push(length as u8);
+
+    result.extend_from_slice(text.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toggle::core::{Goal, RawSection};
+    use crate::toggle::reader;
+
+    fn same_bits(a: &BitArray, b: &BitArray, num_cells: usize) -> bool {
+        (0..num_cells).all(|i| a.get(i) == b.get(i))
+    }
+
+    fn sample_level(width: u32, height: u32) -> ToggleLevel {
+        let mut level = ToggleLevel::new();
+        level.width = width;
+        level.height = height;
+        level.subtypes = (0..level.num_cells()).map(|i| (i % 26) as u8).collect();
+        level.states = BitArray::new(level.num_cells());
+        for cell in (0..level.num_cells()).step_by(3) {
+            level.states.set(cell, true);
+        }
+        level.min_clicks = 7;
+        level
+    }
+
+    #[test]
+    fn round_trip_keeps_the_original_format() {
+        let level = sample_level(3, 2);
+        let parsed = reader::parse(encode(&level).unwrap()).unwrap();
+
+        assert_eq!(parsed.width, 3);
+        assert_eq!(parsed.height, 2);
+        assert_eq!(parsed.subtypes, level.subtypes);
+        assert!(same_bits(&parsed.states, &level.states, 6));
+        assert_eq!(parsed.min_clicks, 7);
+        assert!(parsed.creator.is_empty());
+        assert!(parsed.goal.is_none());
+        assert_eq!(parsed.topology, Topology::Bounded);
+        assert!(parsed.voids.is_none());
+        assert!(parsed.unknown_sections.is_empty());
+    }
+
+    #[test]
+    fn round_trip_keeps_every_section() {
+        let mut level = sample_level(11, 9);
+        let num_cells = level.num_cells();
+        // Long enough to need two length bytes
+        level.creator = "creator ".repeat(20);
+
+        let mut goal = Goal::new(num_cells);
+        goal.target.set(4, true);
+        goal.care.set(5, false);
+        level.goal = Some(goal.clone());
+
+        level.topology = Topology::Cylinder;
+        let mut voids = BitArray::new(num_cells);
+        voids.set(0, true);
+        voids.set(num_cells - 1, true);
+        level.voids = Some(voids.clone());
+        level.ray_mode = RayMode::Skip;
+
+        level.unknown_sections.push(RawSection {
+            tag: 0x7f,
+            data: vec![1, 2, 3],
+        });
+
+        let parsed = reader::parse(encode(&level).unwrap()).unwrap();
+
+        assert_eq!(parsed.subtypes, level.subtypes);
+        assert!(same_bits(&parsed.states, &level.states, num_cells));
+        assert_eq!(parsed.creator, level.creator);
+        let parsed_goal = parsed.goal.unwrap();
+        assert!(same_bits(&parsed_goal.target, &goal.target, num_cells));
+        assert!(same_bits(&parsed_goal.care, &goal.care, num_cells));
+        assert_eq!(parsed.topology, Topology::Cylinder);
+        assert!(same_bits(&parsed.voids.unwrap(), &voids, num_cells));
+        assert_eq!(parsed.ray_mode, RayMode::Skip);
+        assert_eq!(parsed.unknown_sections, level.unknown_sections);
+    }
+
+    #[test]
+    fn largest_board_round_trips() {
+        let level = sample_level(255, 255);
+        let parsed = reader::parse(encode(&level).unwrap()).unwrap();
+
+        assert_eq!(parsed.width, 255);
+        assert_eq!(parsed.height, 255);
+        assert_eq!(parsed.subtypes, level.subtypes);
+    }
+
+    #[test]
+    fn oversized_fields_are_rejected() {
+        let level = sample_level(256, 1);
+        assert!(matches!(
+            write_toggle(&level),
+            Err(EncodeError::WidthTooLarge { width: 256 })
+        ));
+
+        let level = sample_level(1, 300);
+        assert!(matches!(
+            encode(&level),
+            Err(EncodeError::HeightTooLarge { height: 300 })
+        ));
+
+        let mut level = sample_level(2, 2);
+        level.min_clicks = u32::MAX;
+        assert!(matches!(
+            encode(&level),
+            Err(EncodeError::MinClicksTooLarge { .. })
+        ));
+    }
+}